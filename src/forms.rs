@@ -0,0 +1,637 @@
+use crate::match_ok;
+use crate::syntax_tree::{SyntaxTree, into_syntax_list};
+use crate::token_tree::TokenTree;
+use crate::typed_tree::{
+    RuntimeContext, TypeContext, TypeInfo, Value, call_values, compare_values, eval_value,
+    interpret, runtime_error,
+};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+// A form whose operands are typed as ordinary expressions in the scope of the form, so it cannot
+// bind names for them. By default operands are evaluated left to right before `eval` runs;
+// forms that evaluate operands lazily or repeatedly implement `eval_operands` instead
+pub trait SpecialForm {
+    fn name(&self) -> &str;
+
+    // Number of operands, checked against the converted operands when typing
+    fn arity(&self) -> usize;
+
+    // Converts the operands into syntax trees
+    fn operands(&self, error_log: &mut String, args: &[TokenTree]) -> Option<Vec<SyntaxTree>> {
+        into_syntax_list(error_log, args)
+    }
+
+    // Operand and result types; fresh type variables make the form generic
+    fn signature(&self, ctx: &mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo);
+
+    // Extra checks on the resolved operand types
+    fn validate(&self, _args: &[TypeInfo]) -> Result<(), String> {
+        Ok(())
+    }
+
+    fn eval_operands<'a>(
+        &self,
+        ctx: &mut RuntimeContext<'a>,
+        args: &'a [TokenTree],
+    ) -> Result<Value, String> {
+        let mut vals = Vec::new();
+        for x in args {
+            vals.push(interpret(ctx, x)?);
+        }
+        self.eval(ctx, vals)
+    }
+
+    fn eval(&self, _ctx: &mut RuntimeContext<'_>, _args: Vec<Value>) -> Result<Value, String> {
+        Err(format!("`{}` evaluates its own operands", self.name()))
+    }
+}
+
+#[derive(Clone)]
+pub struct Form(pub Rc<dyn SpecialForm>);
+
+impl fmt::Debug for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0.name())
+    }
+}
+
+impl PartialEq for Form {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.name() == other.0.name()
+    }
+}
+
+impl Eq for Form {}
+
+thread_local! {
+    static FORMS: RefCell<HashMap<Rc<str>, Form>> = RefCell::new({
+        let builtins = BUILTINS.iter().map(|x| Form(Rc::new(*x)));
+        let assertions = [Form(Rc::new(Assert)), Form(Rc::new(AssertEq))];
+        builtins.chain(assertions).map(|x| (x.0.name().into(), x)).collect()
+    });
+}
+
+// Forms still built into the parser, the checker and the interpreter, because they bind names,
+// assign to places, take types or have a syntax of their own. Their names are matched before
+// the registry is consulted, so they cannot be registered
+pub const CORE_FORMS: &[&str] = &[
+    "let",
+    "var",
+    "letrec",
+    "fn",
+    "generic",
+    "seq",
+    "set",
+    "+=",
+    "-=",
+    "*=",
+    "/=",
+    "%=",
+    "+",
+    "-",
+    "*",
+    "/",
+    "%",
+    ":",
+    "array",
+    "array-t",
+    "array-get",
+    "array-set",
+    "array-push",
+    "array-pop",
+    "struct",
+    "record",
+    "field-get",
+    "field-set",
+    "enum",
+    "variant",
+    "match",
+    "tuple",
+    "tuple-t",
+    "tuple-get",
+    "map",
+    "map-t",
+    "map-get",
+    "map-contains",
+    "map-len",
+    "map-keys",
+    "map-insert",
+    "map-remove",
+    "fn-t",
+    "quote",
+    "quasiquote",
+    "unquote",
+    "unquote-splicing",
+    "raise",
+    "throw",
+    "?",
+    "try",
+    "import",
+    "deftest",
+    "defmacro",
+];
+
+// Makes the form available to every program parsed afterwards on this thread,
+// replacing any registered form of the same name
+pub fn register(form: Rc<dyn SpecialForm>) -> Result<(), String> {
+    let name = form.name();
+    if CORE_FORMS.contains(&name) {
+        return Err(format!(
+            "`{name}` is a built-in form and cannot be replaced"
+        ));
+    }
+    let name = name.into();
+    FORMS.with_borrow_mut(|forms| forms.insert(name, Form(form)));
+    Ok(())
+}
+
+pub fn lookup(name: &str) -> Option<Form> {
+    FORMS.with_borrow(|forms| forms.get(name).cloned())
+}
+
+#[derive(Clone, Copy)]
+struct Builtin {
+    name: &'static str,
+    arity: usize,
+    signature: fn(&mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo),
+    validate: fn(&[TypeInfo]) -> Result<(), String>,
+    eval: fn(&mut RuntimeContext<'_>, Vec<Value>) -> Result<Value, String>,
+}
+
+impl SpecialForm for Builtin {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn arity(&self) -> usize {
+        self.arity
+    }
+
+    fn signature(&self, ctx: &mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo) {
+        (self.signature)(ctx)
+    }
+
+    fn validate(&self, args: &[TypeInfo]) -> Result<(), String> {
+        (self.validate)(args)
+    }
+
+    fn eval(&self, ctx: &mut RuntimeContext<'_>, args: Vec<Value>) -> Result<Value, String> {
+        (self.eval)(ctx, args)
+    }
+}
+
+// Operands are evaluated here rather than before, as a failure shows their source
+struct Assert;
+
+impl SpecialForm for Assert {
+    fn name(&self) -> &str {
+        "assert"
+    }
+
+    fn arity(&self) -> usize {
+        1
+    }
+
+    fn signature(&self, _: &mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo) {
+        (vec![TypeInfo::Int64], TypeInfo::Unit)
+    }
+
+    fn eval_operands<'a>(
+        &self,
+        ctx: &mut RuntimeContext<'a>,
+        args: &'a [TokenTree],
+    ) -> Result<Value, String> {
+        match interpret(ctx, &args[0])? {
+            Value::Int64(0) => Err(format!("Assertion failed: `{}`", args[0])),
+            _ => Ok(Value::Unit),
+        }
+    }
+}
+
+struct AssertEq;
+
+impl SpecialForm for AssertEq {
+    fn name(&self) -> &str {
+        "assert-eq"
+    }
+
+    fn arity(&self) -> usize {
+        2
+    }
+
+    fn signature(&self, ctx: &mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo) {
+        let a = ctx.fresh_var();
+        (vec![a.clone(), a], TypeInfo::Unit)
+    }
+
+    fn eval_operands<'a>(
+        &self,
+        ctx: &mut RuntimeContext<'a>,
+        args: &'a [TokenTree],
+    ) -> Result<Value, String> {
+        let left = interpret(ctx, &args[0])?;
+        let right = interpret(ctx, &args[1])?;
+        if left == right {
+            return Ok(Value::Unit);
+        }
+        Err(format!(
+            "Assertion failed: `{}` == `{}`\n  left: {left}\n right: {right}",
+            args[0], args[1]
+        ))
+    }
+}
+
+fn array_of(t: &TypeInfo) -> TypeInfo {
+    TypeInfo::Array(Box::new(t.clone()), None)
+}
+
+fn func_of(params: &[&TypeInfo], ret: &TypeInfo) -> TypeInfo {
+    let params = params.iter().map(|&x| x.clone()).collect();
+    TypeInfo::Func(params, Box::new(ret.clone()))
+}
+
+fn no_validation(_: &[TypeInfo]) -> Result<(), String> {
+    Ok(())
+}
+
+// Leaves `Unit` behind so the array is not shared with the argument list
+fn take_array(x: &mut Value) -> Result<Rc<Vec<Value>>, String> {
+    Ok(match_ok!(std::mem::take(x), Value::Array(x) => x)?)
+}
+
+fn take_int(x: &Value) -> Result<i64, String> {
+    Ok(match_ok!(x, &Value::Int64(x) => x)?)
+}
+
+fn truthy(x: Value) -> Result<bool, String> {
+    Ok(take_int(&x)? != 0)
+}
+
+const BUILTINS: &[Builtin] = &[
+    Builtin {
+        name: "array-len",
+        arity: 1,
+        signature: |ctx| (vec![array_of(&ctx.fresh_var())], TypeInfo::Int64),
+        validate: no_validation,
+        eval: |_, mut args| Ok(Value::Int64(take_array(&mut args[0])?.len() as i64)),
+    },
+    Builtin {
+        name: "array-fill",
+        arity: 2,
+        signature: |ctx| {
+            let a = ctx.fresh_var();
+            (vec![TypeInfo::Int64, a.clone()], array_of(&a))
+        },
+        validate: no_validation,
+        eval: |ctx, args| {
+            let len = take_int(&args[0])?;
+            let len = usize::try_from(len).map_err(|_| {
+                runtime_error(&mut ctx.raised, format!("Negative array length {len}"))
+            })?;
+            Ok(Value::Array(Rc::new(vec![args[1].clone(); len])))
+        },
+    },
+    Builtin {
+        name: "array-slice",
+        arity: 3,
+        signature: |ctx| {
+            let a = array_of(&ctx.fresh_var());
+            (vec![a.clone(), TypeInfo::Int64, TypeInfo::Int64], a)
+        },
+        validate: no_validation,
+        eval: |ctx, mut args| {
+            let array = take_array(&mut args[0])?;
+            let (start, end) = (take_int(&args[1])?, take_int(&args[2])?);
+            let len = array.len();
+            let range = usize::try_from(start)
+                .ok()
+                .zip(usize::try_from(end).ok())
+                .filter(|&(x, y)| x <= y && y <= len)
+                .ok_or_else(|| {
+                    let err = format!("Slice {start}..{end} out of bounds for length {len}");
+                    runtime_error(&mut ctx.raised, err)
+                })?;
+            Ok(Value::Array(Rc::new(array[range.0..range.1].to_vec())))
+        },
+    },
+    Builtin {
+        name: "array-concat",
+        arity: 2,
+        signature: |ctx| {
+            let a = array_of(&ctx.fresh_var());
+            (vec![a.clone(), a.clone()], a)
+        },
+        validate: no_validation,
+        eval: |_, mut args| {
+            let mut x = take_array(&mut args[0])?;
+            let y = take_array(&mut args[1])?;
+            Rc::make_mut(&mut x).extend(y.iter().cloned());
+            Ok(Value::Array(x))
+        },
+    },
+    Builtin {
+        name: "array-reverse",
+        arity: 1,
+        signature: |ctx| {
+            let a = array_of(&ctx.fresh_var());
+            (vec![a.clone()], a)
+        },
+        validate: no_validation,
+        eval: |_, mut args| {
+            let mut array = take_array(&mut args[0])?;
+            Rc::make_mut(&mut array).reverse();
+            Ok(Value::Array(array))
+        },
+    },
+    Builtin {
+        name: "array-sort",
+        arity: 1,
+        signature: |ctx| {
+            let a = array_of(&ctx.fresh_var());
+            (vec![a.clone()], a)
+        },
+        validate: |args| match &args[0] {
+            TypeInfo::Array(t, _) if !t.is_ordered() => Err(format!("Type {t} cannot be sorted")),
+            _ => Ok(()),
+        },
+        eval: |_, mut args| {
+            let mut array = take_array(&mut args[0])?;
+            Rc::make_mut(&mut array).sort_by(compare_values);
+            Ok(Value::Array(array))
+        },
+    },
+    Builtin {
+        name: "array-map",
+        arity: 2,
+        signature: |ctx| {
+            let (a, b) = (ctx.fresh_var(), ctx.fresh_var());
+            (vec![func_of(&[&a], &b), array_of(&a)], array_of(&b))
+        },
+        validate: no_validation,
+        eval: |ctx, mut args| {
+            let array = take_array(&mut args[1])?;
+            let mut out = Vec::new();
+            for x in array.iter() {
+                out.push(call_values(ctx, args[0].clone(), vec![x.clone()])?);
+            }
+            Ok(Value::Array(Rc::new(out)))
+        },
+    },
+    Builtin {
+        name: "filter",
+        arity: 2,
+        signature: |ctx| {
+            let a = ctx.fresh_var();
+            (
+                vec![func_of(&[&a], &TypeInfo::Int64), array_of(&a)],
+                array_of(&a),
+            )
+        },
+        validate: no_validation,
+        eval: |ctx, mut args| {
+            let array = take_array(&mut args[1])?;
+            let mut out = Vec::new();
+            for x in array.iter() {
+                if truthy(call_values(ctx, args[0].clone(), vec![x.clone()])?)? {
+                    out.push(x.clone());
+                }
+            }
+            Ok(Value::Array(Rc::new(out)))
+        },
+    },
+    Builtin {
+        name: "fold",
+        arity: 3,
+        signature: |ctx| {
+            let (a, b) = (ctx.fresh_var(), ctx.fresh_var());
+            (vec![func_of(&[&b, &a], &b), b.clone(), array_of(&a)], b)
+        },
+        validate: no_validation,
+        eval: |ctx, mut args| {
+            let array = take_array(&mut args[2])?;
+            let mut acc = std::mem::take(&mut args[1]);
+            for x in array.iter() {
+                acc = call_values(ctx, args[0].clone(), vec![acc, x.clone()])?;
+            }
+            Ok(acc)
+        },
+    },
+    Builtin {
+        name: "any",
+        arity: 2,
+        signature: |ctx| {
+            let a = ctx.fresh_var();
+            (
+                vec![func_of(&[&a], &TypeInfo::Int64), array_of(&a)],
+                TypeInfo::Int64,
+            )
+        },
+        validate: no_validation,
+        eval: |ctx, mut args| {
+            for x in take_array(&mut args[1])?.iter() {
+                if truthy(call_values(ctx, args[0].clone(), vec![x.clone()])?)? {
+                    return Ok(Value::Int64(1));
+                }
+            }
+            Ok(Value::Int64(0))
+        },
+    },
+    Builtin {
+        name: "all",
+        arity: 2,
+        signature: |ctx| {
+            let a = ctx.fresh_var();
+            (
+                vec![func_of(&[&a], &TypeInfo::Int64), array_of(&a)],
+                TypeInfo::Int64,
+            )
+        },
+        validate: no_validation,
+        eval: |ctx, mut args| {
+            for x in take_array(&mut args[1])?.iter() {
+                if !truthy(call_values(ctx, args[0].clone(), vec![x.clone()])?)? {
+                    return Ok(Value::Int64(0));
+                }
+            }
+            Ok(Value::Int64(1))
+        },
+    },
+    Builtin {
+        name: "zip",
+        arity: 2,
+        signature: |ctx| {
+            let (a, b) = (ctx.fresh_var(), ctx.fresh_var());
+            let pair = TypeInfo::Tuple(vec![a.clone(), b.clone()]);
+            (vec![array_of(&a), array_of(&b)], array_of(&pair))
+        },
+        validate: no_validation,
+        eval: |_, mut args| {
+            let xs = take_array(&mut args[0])?;
+            let ys = take_array(&mut args[1])?;
+            let pairs = xs.iter().zip(ys.iter());
+            let out = pairs.map(|(x, y)| Value::Tuple(vec![x.clone(), y.clone()]));
+            Ok(Value::Array(Rc::new(out.collect())))
+        },
+    },
+    Builtin {
+        name: "enumerate",
+        arity: 1,
+        signature: |ctx| {
+            let a = ctx.fresh_var();
+            let pair = TypeInfo::Tuple(vec![TypeInfo::Int64, a.clone()]);
+            (vec![array_of(&a)], array_of(&pair))
+        },
+        validate: no_validation,
+        eval: |_, mut args| {
+            let xs = take_array(&mut args[0])?;
+            let pairs = xs.iter().enumerate();
+            let out = pairs.map(|(i, x)| Value::Tuple(vec![Value::Int64(i as i64), x.clone()]));
+            Ok(Value::Array(Rc::new(out.collect())))
+        },
+    },
+    Builtin {
+        name: "range",
+        arity: 2,
+        signature: |_| {
+            let int = TypeInfo::Int64;
+            (vec![int.clone(), int.clone()], array_of(&int))
+        },
+        validate: no_validation,
+        eval: |_, args| {
+            let (start, end) = (take_int(&args[0])?, take_int(&args[1])?);
+            Ok(Value::Array(Rc::new(
+                (start..end).map(Value::Int64).collect(),
+            )))
+        },
+    },
+    Builtin {
+        name: "eval",
+        arity: 2,
+        signature: |ctx| {
+            let a = ctx.fresh_var();
+            (vec![TypeInfo::Sexp, TypeInfo::Type(Box::new(a.clone()))], a)
+        },
+        validate: no_validation,
+        eval: |ctx, args| {
+            let t = match_ok!(&args[1], Value::Type(x) => x)?;
+            eval_value(ctx, &args[0], t)
+        },
+    },
+    Builtin {
+        name: "symbol",
+        arity: 1,
+        signature: |_| (vec![TypeInfo::Str], TypeInfo::Sexp),
+        validate: no_validation,
+        eval: |_, args| {
+            Ok(Value::Symbol(
+                match_ok!(&args[0], Value::Str(x) => x.clone())?,
+            ))
+        },
+    },
+    Builtin {
+        name: "symbol-name",
+        arity: 1,
+        signature: |_| (vec![TypeInfo::Sexp], TypeInfo::Str),
+        validate: no_validation,
+        eval: |_, args| match &args[0] {
+            Value::Symbol(x) => Ok(Value::Str(x.clone())),
+            x => Err(format!("{x:?} is not a symbol")),
+        },
+    },
+    // Items of a quoted list, so that code can be taken apart with the array forms
+    Builtin {
+        name: "sexp-items",
+        arity: 1,
+        signature: |_| (vec![TypeInfo::Sexp], array_of(&TypeInfo::Sexp)),
+        validate: no_validation,
+        eval: |_, mut args| match std::mem::take(&mut args[0]) {
+            Value::List(items) => Ok(Value::Array(items)),
+            x => Err(format!("{x:?} is not a list")),
+        },
+    },
+    Builtin {
+        name: "sexp-list",
+        arity: 1,
+        signature: |_| (vec![array_of(&TypeInfo::Sexp)], TypeInfo::Sexp),
+        validate: no_validation,
+        eval: |_, mut args| Ok(Value::List(take_array(&mut args[0])?)),
+    },
+];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::typed_tree::{TypedTree, interpret_no_context};
+
+    // Evaluates both operands again on every turn, until the condition is zero
+    struct While;
+
+    impl SpecialForm for While {
+        fn name(&self) -> &str {
+            "while"
+        }
+
+        fn arity(&self) -> usize {
+            2
+        }
+
+        fn signature(&self, ctx: &mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo) {
+            (vec![TypeInfo::Int64, ctx.fresh_var()], TypeInfo::Unit)
+        }
+
+        fn eval_operands<'a>(
+            &self,
+            ctx: &mut RuntimeContext<'a>,
+            args: &'a [TokenTree],
+        ) -> Result<Value, String> {
+            while truthy(interpret(ctx, &args[0])?)? {
+                interpret(ctx, &args[1])?;
+            }
+            Ok(Value::Unit)
+        }
+    }
+
+    #[test]
+    fn registered_lazy_form() {
+        register(Rc::new(While)).unwrap();
+        let src = "(var i i64 (var s i64 (seq (while (- 5 i) (seq (+= s i) (+= i 1))) s)))";
+        let tokens: TokenTree = src.parse().unwrap();
+        let typed = TypedTree::try_from(&SyntaxTree::try_from(&tokens).unwrap()).unwrap();
+        assert_eq!(*typed.type_info(), TypeInfo::Int64);
+        assert_eq!(interpret_no_context(&tokens), Ok(Value::Int64(10)));
+    }
+
+    struct Named(&'static str);
+
+    impl SpecialForm for Named {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn arity(&self) -> usize {
+            0
+        }
+
+        fn signature(&self, _ctx: &mut TypeContext<'_>) -> (Vec<TypeInfo>, TypeInfo) {
+            (vec![], TypeInfo::Int64)
+        }
+    }
+
+    // Every core form is matched before the registry, none of them parses as a call
+    #[test]
+    fn core_forms_cannot_be_registered() {
+        for &name in CORE_FORMS {
+            assert!(register(Rc::new(Named(name))).is_err(), "{name}");
+            assert!(lookup(name).is_none(), "{name}");
+            let tokens: TokenTree = format!("({name})").parse().unwrap();
+            let parsed = SyntaxTree::try_from(&tokens);
+            assert!(
+                !matches!(parsed, Ok(SyntaxTree::Call(_, _) | SyntaxTree::Form(_, _))),
+                "{name} parsed as {parsed:?}"
+            );
+        }
+    }
+}
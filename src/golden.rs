@@ -0,0 +1,112 @@
+// Golden-file tests: every `tests/golden/*.lisp` program goes through each stage of the
+// pipeline, and the outputs and diagnostics are compared with the `.expected` file next to it.
+// Run `BLESS=1 cargo test golden` to write the current outputs instead.
+use crate::modules::set_search_path;
+use crate::syntax_tree::SyntaxTree;
+use crate::token_tree::TokenTree;
+use crate::typed_tree::{TypedTree, interpret_no_context};
+use std::fmt::Write;
+use std::path::{Path, PathBuf};
+
+const DIR: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+// Writes the section of one stage, returning its output only when the stage succeeded
+fn stage<T>(
+    out: &mut String,
+    name: &str,
+    res: Result<T, String>,
+    show: fn(&T) -> String,
+) -> Option<T> {
+    writeln!(out, "-- {name}").unwrap();
+    match res {
+        Ok(x) => {
+            writeln!(out, "{}", show(&x)).unwrap();
+            Some(x)
+        }
+        Err(err) => {
+            writeln!(out, "error: {}", err.trim_end()).unwrap();
+            None
+        }
+    }
+}
+
+// Stages after the first failing one are left out
+fn run_stages(out: &mut String, source: &str) -> Option<()> {
+    let tokens = stage(out, "tokens", source.parse::<TokenTree>(), |x| {
+        x.to_string()
+    })?;
+    let syntax_tree = stage(out, "syntax", SyntaxTree::try_from(&tokens), |x| {
+        x.to_string()
+    })?;
+    let typed = TypedTree::try_from(&syntax_tree);
+    stage(out, "typed", typed, |x| x.type_info().to_string())?;
+    stage(out, "eval", interpret_no_context(&tokens), |x| {
+        x.to_string()
+    })?;
+    Some(())
+}
+
+fn programs() -> Vec<PathBuf> {
+    let entries = std::fs::read_dir(DIR).expect("the golden directory exists");
+    let mut out: Vec<_> = entries
+        .map(|x| x.unwrap().path())
+        .filter(|x| x.extension().is_some_and(|x| x == "lisp"))
+        .collect();
+    out.sort();
+    out
+}
+
+// Returns a description of each program whose output differs from its expected file
+fn check_all(bless: bool) -> Vec<String> {
+    // Module files imported by the programs live apart so they are not run on their own
+    set_search_path(vec![Path::new(DIR).join("modules")]);
+    let mut failures = Vec::new();
+    for path in programs() {
+        let source = std::fs::read_to_string(&path).unwrap();
+        let mut actual = String::new();
+        run_stages(&mut actual, &source);
+        // Guard failures name a line of the interpreter source; diagnostics for the
+        // user must not, so these fail even when blessing
+        if actual.contains(".rs:") {
+            failures.push(format!(
+                "{}: diagnostic points into the interpreter source\nactual:\n{actual}",
+                path.display()
+            ));
+            continue;
+        }
+        let expected_path = path.with_extension("expected");
+        if bless {
+            std::fs::write(&expected_path, &actual).unwrap();
+            continue;
+        }
+        match std::fs::read_to_string(&expected_path) {
+            Ok(expected) if expected == actual => {}
+            Ok(expected) => failures.push(format!(
+                "{}\nexpected:\n{expected}actual:\n{actual}",
+                path.display()
+            )),
+            Err(_) => failures.push(format!(
+                "{}: no {}\nactual:\n{actual}",
+                path.display(),
+                expected_path.display()
+            )),
+        }
+    }
+    failures
+}
+
+#[test]
+fn golden() {
+    let bless = std::env::var_os("BLESS").is_some();
+    // Loops are written as recursion, which needs a deeper stack than test threads get
+    let thread = std::thread::Builder::new()
+        .stack_size(1 << 28)
+        .spawn(move || check_all(bless));
+    let failures = thread.unwrap().join().unwrap();
+    assert!(
+        failures.is_empty(),
+        "{}\n{} golden file(s) differ, run with BLESS=1 to update them",
+        failures.join("\n"),
+        failures.len()
+    );
+}
@@ -0,0 +1,184 @@
+use crate::typed_tree::{EnumType, StructType, TypeContext, TypeInfo};
+use std::rc::Rc;
+
+impl TypeInfo {
+    fn map_children(&self, mut f: impl FnMut(&TypeInfo) -> TypeInfo) -> TypeInfo {
+        match self {
+            Self::Type(t) => Self::Type(Box::new(f(t))),
+            Self::Array(t, len) => Self::Array(Box::new(f(t)), *len),
+            Self::Tuple(items) => Self::Tuple(items.iter().map(f).collect()),
+            Self::Map(k, v) => Self::Map(Box::new(f(k)), Box::new(f(v))),
+            Self::Func(params, ret) => {
+                let params = params.iter().map(&mut f).collect();
+                Self::Func(params, Box::new(f(ret)))
+            }
+            Self::Forall(vars, t) => Self::Forall(vars.clone(), Box::new(f(t))),
+            Self::Struct(s) => Self::Struct(Rc::new(StructType {
+                id: s.id,
+                params: s.params.iter().map(&mut f).collect(),
+                fields: s.fields.iter().map(|(x, t)| (x.clone(), f(t))).collect(),
+            })),
+            Self::Enum(e) => Self::Enum(Rc::new(EnumType {
+                id: e.id,
+                params: e.params.iter().map(&mut f).collect(),
+                variants: e
+                    .variants
+                    .iter()
+                    .map(|(x, payload)| (x.clone(), payload.iter().map(&mut f).collect()))
+                    .collect(),
+            })),
+            _ => self.clone(),
+        }
+    }
+
+    fn for_each_child(&self, mut f: impl FnMut(&TypeInfo)) {
+        self.map_children(|x| {
+            f(x);
+            TypeInfo::Unit
+        });
+    }
+
+    pub fn free_vars(&self, out: &mut Vec<usize>) {
+        match self {
+            Self::Var(x) => {
+                if !out.contains(x) {
+                    out.push(*x);
+                }
+            }
+            Self::Forall(vars, t) => {
+                let mut inner = Vec::new();
+                t.free_vars(&mut inner);
+                for x in inner {
+                    if !vars.contains(&x) && !out.contains(&x) {
+                        out.push(x);
+                    }
+                }
+            }
+            _ => self.for_each_child(|x| x.free_vars(out)),
+        }
+    }
+
+    pub fn substitute(&self, map: &[(usize, TypeInfo)]) -> TypeInfo {
+        match self {
+            Self::Var(x) => match map.iter().find(|(y, _)| x == y) {
+                Some((_, t)) => t.clone(),
+                None => self.clone(),
+            },
+            _ => self.map_children(|x| x.substitute(map)),
+        }
+    }
+
+    pub fn erase_lengths(&self) -> TypeInfo {
+        match self {
+            Self::Array(t, _) => Self::Array(Box::new(t.erase_lengths()), None),
+            _ => self.map_children(|x| x.erase_lengths()),
+        }
+    }
+}
+
+impl TypeContext<'_> {
+    pub fn fresh_var(&mut self) -> TypeInfo {
+        self.substitution.push(None);
+        TypeInfo::Var(self.substitution.len() - 1)
+    }
+
+    // Applies the current substitution everywhere inside the type
+    pub fn resolve(&self, t: &TypeInfo) -> TypeInfo {
+        match t {
+            TypeInfo::Var(x) => match &self.substitution[*x] {
+                Some(t) => self.resolve(t),
+                None => t.clone(),
+            },
+            _ => t.map_children(|x| self.resolve(x)),
+        }
+    }
+
+    fn resolve_shallow(&self, t: &TypeInfo) -> TypeInfo {
+        match t {
+            TypeInfo::Var(x) => match &self.substitution[*x] {
+                Some(t) => self.resolve_shallow(t),
+                None => t.clone(),
+            },
+            _ => t.clone(),
+        }
+    }
+
+    // On failure the substitution may be left partially updated, which only affects
+    // the wording of follow-up errors
+    // Directional for array lengths: a value of fixed length fits where the expected length is
+    // unknown, but not the other way around
+    pub fn unify(&mut self, a: &TypeInfo, b: &TypeInfo) -> bool {
+        let a = self.resolve_shallow(a);
+        let b = self.resolve_shallow(b);
+        match (&a, &b) {
+            (TypeInfo::Var(x), TypeInfo::Var(y)) if x == y => true,
+            (&TypeInfo::Var(x), t) | (t, &TypeInfo::Var(x)) => {
+                let mut vars = Vec::new();
+                self.resolve(t).free_vars(&mut vars);
+                if vars.contains(&x) {
+                    return false;
+                }
+                self.substitution[x] = Some(t.clone());
+                true
+            }
+            (TypeInfo::Type(x), TypeInfo::Type(y)) => self.unify(x, y),
+            (TypeInfo::Array(x, n), TypeInfo::Array(y, m)) => {
+                (n.is_none() || n == m) && self.unify(x, y)
+            }
+            (TypeInfo::Tuple(xs), TypeInfo::Tuple(ys)) => self.unify_all(xs, ys),
+            (TypeInfo::Map(k1, v1), TypeInfo::Map(k2, v2)) => {
+                self.unify(k1, k2) && self.unify(v1, v2)
+            }
+            // Fields of generic types are determined by their arguments
+            (TypeInfo::Struct(s1), TypeInfo::Struct(s2)) => {
+                s1.id == s2.id && self.unify_all(&s1.params, &s2.params)
+            }
+            (TypeInfo::Enum(e1), TypeInfo::Enum(e2)) => {
+                e1.id == e2.id && self.unify_all(&e1.params, &e2.params)
+            }
+            // Parameters go the other way: the function found is passed what the expected one is
+            (TypeInfo::Func(ps1, r1), TypeInfo::Func(ps2, r2)) => {
+                self.unify_all(ps2, ps1) && self.unify(r1, r2)
+            }
+            _ => a == b,
+        }
+    }
+
+    fn unify_all(&mut self, xs: &[TypeInfo], ys: &[TypeInfo]) -> bool {
+        xs.len() == ys.len() && xs.iter().zip(ys).all(|(x, y)| self.unify(x, y))
+    }
+
+    // Quantifies over the variables that do not occur in the environment
+    pub fn generalize(&self, t: &TypeInfo) -> TypeInfo {
+        let (mut vars, t) = match self.resolve(t) {
+            TypeInfo::Forall(vars, t) => (vars, *t),
+            t => (Vec::new(), t),
+        };
+        let mut env_vars = Vec::new();
+        for x in self.variables.values().flatten() {
+            self.resolve(x).free_vars(&mut env_vars);
+        }
+        let mut free = Vec::new();
+        t.free_vars(&mut free);
+        for x in free {
+            if !env_vars.contains(&x) && !vars.contains(&x) {
+                vars.push(x);
+            }
+        }
+        if vars.is_empty() {
+            t
+        } else {
+            TypeInfo::Forall(vars, Box::new(t))
+        }
+    }
+
+    pub fn instantiate(&mut self, t: &TypeInfo) -> TypeInfo {
+        match t {
+            TypeInfo::Forall(vars, t) => {
+                let map: Vec<_> = vars.iter().map(|&x| (x, self.fresh_var())).collect();
+                t.substitute(&map)
+            }
+            _ => t.clone(),
+        }
+    }
+}
@@ -0,0 +1,514 @@
+use crate::token_tree::TokenTree;
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::rc::Rc;
+
+const MAX_DEPTH: usize = 256;
+
+// `(defmacro name (syntax-rules (literal ...) (pattern template) ...) body)`
+struct Macro {
+    name: Rc<str>,
+    literals: Vec<Rc<str>>,
+    rules: Vec<(TokenTree, TokenTree)>,
+    definition: TokenTree,
+}
+
+#[derive(Debug, Clone)]
+enum Binding {
+    One(TokenTree),
+    // One entry per repetition of the enclosing `...`
+    Many(Vec<Binding>),
+}
+
+#[derive(Default)]
+struct ExpandContext {
+    error_log: String,
+    macros: HashMap<Rc<str>, Rc<Macro>>,
+    fresh_count: usize,
+    depth: usize,
+}
+
+fn is_ellipsis(tree: &TokenTree) -> bool {
+    matches!(tree, TokenTree::Atom(x) if &x[..] == "...")
+}
+
+fn same_atom(a: &TokenTree, b: &TokenTree) -> bool {
+    match (a, b) {
+        (TokenTree::Atom(x), TokenTree::Atom(y)) => x == y,
+        (TokenTree::Int64(x), TokenTree::Int64(y)) => x == y,
+        (TokenTree::Str(x), TokenTree::Str(y)) => x == y,
+        _ => false,
+    }
+}
+
+fn truncated(tree: &TokenTree) -> String {
+    let mut s = tree.to_string();
+    if s.len() > 60 {
+        let end = (0..=60).rev().find(|&i| s.is_char_boundary(i)).unwrap_or(0);
+        s.truncate(end);
+        s.push_str("...");
+    }
+    s
+}
+
+impl Macro {
+    fn is_var(&self, x: &str) -> bool {
+        x != "_" && x != "..." && !self.literals.iter().any(|y| &y[..] == x)
+    }
+
+    fn pattern_vars(&self, pattern: &TokenTree, out: &mut Vec<Rc<str>>) {
+        match pattern {
+            TokenTree::Atom(x) if self.is_var(x) => out.push(x.clone()),
+            TokenTree::Array(items) => {
+                for it in items {
+                    self.pattern_vars(it, out);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn match_pattern(
+        &self,
+        pattern: &TokenTree,
+        tree: &TokenTree,
+        out: &mut HashMap<Rc<str>, Binding>,
+    ) -> bool {
+        match pattern {
+            TokenTree::Atom(x) if &x[..] == "_" => true,
+            TokenTree::Atom(x) if self.is_var(x) => {
+                out.insert(x.clone(), Binding::One(tree.clone()));
+                true
+            }
+            TokenTree::Array(patterns) => match tree {
+                TokenTree::Array(items) => self.match_list(patterns, items, out),
+                _ => false,
+            },
+            _ => same_atom(pattern, tree),
+        }
+    }
+
+    fn match_list(
+        &self,
+        patterns: &[TokenTree],
+        items: &[TokenTree],
+        out: &mut HashMap<Rc<str>, Binding>,
+    ) -> bool {
+        let pos = patterns.iter().position(is_ellipsis);
+        // A leading `...` repeats nothing, definitions with one are rejected
+        if pos == Some(0) {
+            return false;
+        }
+        let Some(pos) = pos else {
+            return patterns.len() == items.len()
+                && patterns
+                    .iter()
+                    .zip(items)
+                    .all(|(p, x)| self.match_pattern(p, x, out));
+        };
+        // `p ...` takes whatever is left once the patterns around it are matched
+        let (before, after) = (&patterns[..pos - 1], &patterns[pos + 1..]);
+        if items.len() < before.len() + after.len() {
+            return false;
+        }
+        let repeated_end = items.len() - after.len();
+        let fixed_ok = before
+            .iter()
+            .zip(items)
+            .all(|(p, x)| self.match_pattern(p, x, out))
+            && after
+                .iter()
+                .zip(&items[repeated_end..])
+                .all(|(p, x)| self.match_pattern(p, x, out));
+        if !fixed_ok {
+            return false;
+        }
+        let mut vars = Vec::new();
+        self.pattern_vars(&patterns[pos - 1], &mut vars);
+        let mut seqs = vec![Vec::new(); vars.len()];
+        for it in &items[before.len()..repeated_end] {
+            let mut inner = HashMap::new();
+            if !self.match_pattern(&patterns[pos - 1], it, &mut inner) {
+                return false;
+            }
+            for (var, seq) in vars.iter().zip(&mut seqs) {
+                seq.push(inner.remove(var).unwrap());
+            }
+        }
+        for (var, seq) in vars.into_iter().zip(seqs) {
+            out.insert(var, Binding::Many(seq));
+        }
+        true
+    }
+}
+
+// Names the template itself binds with `let`, `var`, `letrec`, `fn`, `generic`, `match` arms or
+// `catch`; these get renamed on every expansion so they cannot capture identifiers passed in by
+// the user
+fn template_binders(m: &Macro, template: &TokenTree, out: &mut Vec<Rc<str>>) {
+    let TokenTree::Array(items) = template else {
+        return;
+    };
+    let mut push_binder = |tree: &TokenTree| {
+        if let TokenTree::Atom(x) = tree
+            && m.is_var(x)
+            && !out.contains(x)
+        {
+            out.push(x.clone());
+        }
+    };
+    match (items.first(), items.get(1)) {
+        (Some(TokenTree::Atom(head)), Some(pattern)) if &head[..] == "let" => {
+            pattern_binders(pattern, false, &mut push_binder);
+        }
+        (Some(TokenTree::Atom(head)), Some(name)) if matches!(&head[..], "var" | "letrec") => {
+            push_binder(name);
+        }
+        (Some(TokenTree::Atom(head)), Some(TokenTree::Array(params)))
+            if matches!(&head[..], "fn" | "generic") =>
+        {
+            for it in params {
+                match it {
+                    TokenTree::Array(pair) if !pair.is_empty() => push_binder(&pair[0]),
+                    _ => push_binder(it),
+                }
+            }
+        }
+        (Some(TokenTree::Atom(head)), Some(_)) if &head[..] == "match" => {
+            for arm in &items[2..] {
+                if let TokenTree::Array(arm) = arm
+                    && let Some(pattern) = arm.first()
+                {
+                    pattern_binders(pattern, true, &mut push_binder);
+                }
+            }
+        }
+        (Some(TokenTree::Atom(head)), Some(_)) if &head[..] == "try" => {
+            for clause in &items[2..] {
+                if let TokenTree::Array(clause) = clause
+                    && let [TokenTree::Atom(kind), TokenTree::Array(binding), ..] = &clause[..]
+                    && &kind[..] == "catch"
+                    && let Some(var) = binding.first()
+                {
+                    push_binder(var);
+                }
+            }
+        }
+        _ => {}
+    }
+    for it in items {
+        template_binders(m, it, out);
+    }
+}
+
+// Names bound by a pattern; the first item of a `match` pattern list names the variant
+// or `tuple`, while `let` patterns are lists of patterns only
+fn pattern_binders(pattern: &TokenTree, has_head: bool, push: &mut impl FnMut(&TokenTree)) {
+    match pattern {
+        TokenTree::Array(items) => {
+            for it in items.iter().skip(usize::from(has_head)) {
+                pattern_binders(it, has_head, push);
+            }
+        }
+        _ => push(pattern),
+    }
+}
+
+impl ExpandContext {
+    fn expand(&mut self, tree: &TokenTree) -> Option<TokenTree> {
+        let TokenTree::Array(items) = tree else {
+            return Some(tree.clone());
+        };
+        match items.first() {
+            Some(TokenTree::Atom(head)) if &head[..] == "defmacro" => self.defmacro(tree, items),
+            // Quoted code is data, so only the unquoted holes of a template get expanded
+            Some(TokenTree::Atom(head)) if &head[..] == "quote" => Some(tree.clone()),
+            Some(TokenTree::Atom(head)) if &head[..] == "quasiquote" => self.expand_quasi(tree, 0),
+            Some(TokenTree::Atom(head)) if self.macros.contains_key(head) => {
+                let m = self.macros[head].clone();
+                let expanded = self.apply(&m, tree, items)?;
+                if self.depth >= MAX_DEPTH {
+                    writeln!(
+                        &mut self.error_log,
+                        "Expansion of macro `{}` nests deeper than {MAX_DEPTH} levels in `{}`",
+                        m.name,
+                        truncated(tree)
+                    )
+                    .unwrap();
+                    return None;
+                }
+                self.depth += 1;
+                let out = self.expand(&expanded);
+                self.depth -= 1;
+                out
+            }
+            _ => {
+                let mut out_opt = Some(Vec::new());
+                for it in items {
+                    let it_opt = self.expand(it);
+                    match (&mut out_opt, it_opt) {
+                        (Some(out), Some(it)) => out.push(it),
+                        _ => out_opt = None,
+                    }
+                }
+                Some(TokenTree::Array(out_opt?))
+            }
+        }
+    }
+
+    // `depth` counts the enclosing `quasiquote`s, holes are expanded where it drops back to zero
+    fn expand_quasi(&mut self, tree: &TokenTree, depth: usize) -> Option<TokenTree> {
+        let TokenTree::Array(items) = tree else {
+            return Some(tree.clone());
+        };
+        let head = match items.first() {
+            Some(TokenTree::Atom(head)) => &head[..],
+            _ => "",
+        };
+        let mut out_opt = Some(Vec::new());
+        for (i, it) in items.iter().enumerate() {
+            let it_opt = match head {
+                _ if i == 0 => self.expand_quasi(it, depth),
+                "quasiquote" => self.expand_quasi(it, depth + 1),
+                "unquote" | "unquote-splicing" if depth == 1 => self.expand(it),
+                "unquote" | "unquote-splicing" => self.expand_quasi(it, depth - 1),
+                _ => self.expand_quasi(it, depth),
+            };
+            match (&mut out_opt, it_opt) {
+                (Some(out), Some(it)) => out.push(it),
+                _ => out_opt = None,
+            }
+        }
+        Some(TokenTree::Array(out_opt?))
+    }
+
+    fn defmacro(&mut self, tree: &TokenTree, items: &[TokenTree]) -> Option<TokenTree> {
+        let m_opt = parse_macro(&mut self.error_log, tree, items);
+        let body = items.get(3);
+        let m = m_opt?;
+        let name = m.name.clone();
+        let old = self.macros.insert(name.clone(), Rc::new(m));
+        let out = self.expand(body?);
+        match old {
+            Some(old) => self.macros.insert(name, old),
+            None => self.macros.remove(&name),
+        };
+        out
+    }
+
+    fn apply(&mut self, m: &Macro, tree: &TokenTree, items: &[TokenTree]) -> Option<TokenTree> {
+        for (pattern, template) in &m.rules {
+            let TokenTree::Array(patterns) = pattern else {
+                continue;
+            };
+            let mut bindings = HashMap::new();
+            // The head of the pattern stands for the macro name and is not matched
+            if !m.match_list(&patterns[1..], &items[1..], &mut bindings) {
+                continue;
+            }
+            let mut binders = Vec::new();
+            template_binders(m, template, &mut binders);
+            self.fresh_count += 1;
+            let renames: HashMap<_, _> = binders
+                .into_iter()
+                .map(|x| {
+                    let fresh: Rc<str> = format!("{x}#{}", self.fresh_count).into();
+                    (x, TokenTree::Atom(fresh))
+                })
+                .collect();
+            return match instantiate(m, template, &bindings, &renames) {
+                Ok(out) => Some(out),
+                Err(err) => {
+                    writeln!(
+                        &mut self.error_log,
+                        "Cannot expand macro `{}` in `{}`: {err}",
+                        m.name,
+                        truncated(tree)
+                    )
+                    .unwrap();
+                    writeln!(
+                        &mut self.error_log,
+                        "note: macro `{}` defined here: `{}`",
+                        m.name,
+                        truncated(&m.definition)
+                    )
+                    .unwrap();
+                    None
+                }
+            };
+        }
+        writeln!(
+            &mut self.error_log,
+            "No rule of macro `{}` matches `{}`",
+            m.name,
+            truncated(tree)
+        )
+        .unwrap();
+        writeln!(
+            &mut self.error_log,
+            "note: macro `{}` defined here: `{}`",
+            m.name,
+            truncated(&m.definition)
+        )
+        .unwrap();
+        None
+    }
+}
+
+fn parse_macro(error_log: &mut String, tree: &TokenTree, items: &[TokenTree]) -> Option<Macro> {
+    let fail = |error_log: &mut String, what: &str| {
+        writeln!(
+            error_log,
+            "Malformed macro definition `{}`: {what}",
+            truncated(tree)
+        )
+        .unwrap();
+        None
+    };
+    if items.len() != 4 {
+        return fail(
+            error_log,
+            "expected (defmacro name (syntax-rules ...) body)",
+        );
+    }
+    let TokenTree::Atom(name) = &items[1] else {
+        return fail(error_log, "the name must be an identifier");
+    };
+    let rules_tree = match &items[2] {
+        TokenTree::Array(x) if matches!(x.first(), Some(TokenTree::Atom(h)) if &h[..] == "syntax-rules") => {
+            x
+        }
+        _ => return fail(error_log, "expected (syntax-rules (literal ...) rule ...)"),
+    };
+    let mut literals = Vec::new();
+    match rules_tree.get(1) {
+        Some(TokenTree::Array(xs)) => {
+            for x in xs {
+                let TokenTree::Atom(x) = x else {
+                    return fail(error_log, "literals must be identifiers");
+                };
+                literals.push(x.clone());
+            }
+        }
+        _ => return fail(error_log, "expected a list of literals after syntax-rules"),
+    }
+    let mut m = Macro {
+        name: name.clone(),
+        literals,
+        rules: Vec::new(),
+        definition: items[2].clone(),
+    };
+    for rule in &rules_tree[2..] {
+        let (pattern, template) = match rule {
+            TokenTree::Array(pair) if pair.len() == 2 => (&pair[0], &pair[1]),
+            _ => return fail(error_log, "each rule must be (pattern template)"),
+        };
+        let TokenTree::Array(patterns) = pattern else {
+            return fail(error_log, "each pattern must be a list");
+        };
+        if patterns.is_empty() {
+            return fail(
+                error_log,
+                "each pattern must start with the macro name or _",
+            );
+        }
+        // The head is not matched, so a `...` right after it has nothing to repeat
+        if let Err(what) = check_ellipses(&patterns[1..]) {
+            return fail(error_log, what);
+        }
+        let mut vars = Vec::new();
+        m.pattern_vars(&TokenTree::Array(patterns[1..].to_vec()), &mut vars);
+        if let Some(x) = vars
+            .iter()
+            .enumerate()
+            .find(|(i, x)| vars[..*i].contains(x))
+        {
+            let what = format!("pattern variable {} is used twice", x.1);
+            return fail(error_log, &what);
+        }
+        m.rules.push((pattern.clone(), template.clone()));
+    }
+    Some(m)
+}
+
+fn check_ellipses(items: &[TokenTree]) -> Result<(), &'static str> {
+    if items.first().is_some_and(is_ellipsis) {
+        return Err("`...` must follow a pattern");
+    }
+    if items.iter().filter(|x| is_ellipsis(x)).count() > 1 {
+        return Err("`...` can appear at most once per list");
+    }
+    for it in items {
+        if let TokenTree::Array(inner) = it {
+            check_ellipses(inner)?;
+        }
+    }
+    Ok(())
+}
+
+fn instantiate(
+    m: &Macro,
+    template: &TokenTree,
+    bindings: &HashMap<Rc<str>, Binding>,
+    renames: &HashMap<Rc<str>, TokenTree>,
+) -> Result<TokenTree, String> {
+    let items = match template {
+        TokenTree::Atom(x) => {
+            return match bindings.get(x) {
+                Some(Binding::One(tree)) => Ok(tree.clone()),
+                Some(Binding::Many(_)) => {
+                    Err(format!("pattern variable {x} must be followed by `...`"))
+                }
+                None => Ok(renames.get(x).cloned().unwrap_or_else(|| template.clone())),
+            };
+        }
+        TokenTree::Array(items) => items,
+        _ => return Ok(template.clone()),
+    };
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < items.len() {
+        let it = &items[i];
+        if !items.get(i + 1).is_some_and(is_ellipsis) {
+            out.push(instantiate(m, it, bindings, renames)?);
+            i += 1;
+            continue;
+        }
+        let mut vars = Vec::new();
+        m.pattern_vars(it, &mut vars);
+        let repeated: Vec<_> = vars
+            .iter()
+            .filter_map(|x| match bindings.get(x) {
+                Some(Binding::Many(seq)) => Some((x, seq)),
+                _ => None,
+            })
+            .collect();
+        let Some(&(_, first)) = repeated.first() else {
+            return Err(format!(
+                "`...` follows {it}, which has no repeated pattern variables"
+            ));
+        };
+        if let Some((x, seq)) = repeated.iter().find(|(_, seq)| seq.len() != first.len()) {
+            let (y, len) = (repeated[0].0, first.len());
+            let x_len = seq.len();
+            return Err(format!(
+                "{y} repeats {len} times but {x} repeats {x_len} times"
+            ));
+        }
+        for k in 0..first.len() {
+            let mut inner = bindings.clone();
+            for (x, seq) in &repeated {
+                inner.insert((*x).clone(), seq[k].clone());
+            }
+            out.push(instantiate(m, it, &inner, renames)?);
+        }
+        i += 2;
+    }
+    Ok(TokenTree::Array(out))
+}
+
+// Removes every `defmacro` and replaces macro uses with their expansions
+pub fn expand(tree: &TokenTree) -> Result<TokenTree, String> {
+    let mut ctx = ExpandContext::default();
+    ctx.expand(tree).ok_or(ctx.error_log)
+}
@@ -1,11 +1,25 @@
 #![allow(unused)]
 
+mod forms;
+#[cfg(test)]
+mod golden;
+mod infer;
+mod macros;
+mod modules;
+mod pattern;
+#[cfg(test)]
+mod random_programs;
 mod syntax_tree;
+mod testing;
 mod token_tree;
 mod typed_tree;
 mod util;
+mod value_map;
 
+use crate::modules::{PRELUDE, set_prelude};
+use crate::testing::{TestResult, passed, report, run_tests};
 use crate::typed_tree::parse_interpret;
+use std::path::Path;
 
 #[derive(Debug, Clone, Default)]
 struct MyApp {
@@ -14,6 +28,9 @@ struct MyApp {
     last_out: String,
     last_err: String,
     last_ok: bool,
+    use_prelude: bool,
+    // Tests of the input when it is a module, from the last run
+    test_results: Option<Result<Vec<TestResult>, String>>,
 }
 
 impl MyApp {
@@ -24,6 +41,8 @@ impl MyApp {
             last_out: "".into(),
             last_err: "".into(),
             last_ok: false,
+            use_prelude: true,
+            test_results: None,
         }
     }
 }
@@ -31,6 +50,11 @@ impl MyApp {
 impl eframe::App for MyApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
         egui::Window::new("Input").show(ctx, |ui| {
+            if ui.checkbox(&mut self.use_prelude, "Prelude").changed() {
+                // The bundled prelude always parses
+                _ = set_prelude(self.use_prelude.then_some(PRELUDE));
+                self.input_changed = true;
+            }
             self.input_changed |= ui.code_editor(&mut self.text_input).changed();
         });
         if self.input_changed {
@@ -62,10 +86,67 @@ impl eframe::App for MyApp {
                     .color(egui::Color32::RED),
             );
         });
+        egui::Window::new("Tests").show(ctx, |ui| {
+            if ui.button("Run tests").clicked() {
+                self.test_results = Some(run_tests("input", &self.text_input));
+            }
+            match &self.test_results {
+                None => {}
+                Some(Err(err)) => {
+                    ui.label(
+                        egui::RichText::new(err)
+                            .monospace()
+                            .color(egui::Color32::RED),
+                    );
+                }
+                Some(Ok(results)) => {
+                    for it in results {
+                        let (line, col) = it.span;
+                        let (status, color) = match &it.outcome {
+                            Ok(()) => ("ok", egui::Color32::DARK_GREEN),
+                            Err(_) => ("FAILED", egui::Color32::RED),
+                        };
+                        let text = format!("{line}:{col} {} ... {status}", it.name);
+                        ui.label(egui::RichText::new(text).monospace().color(color));
+                        if let Err(err) = &it.outcome {
+                            ui.label(egui::RichText::new(err.trim_end()).monospace());
+                        }
+                    }
+                }
+            }
+        });
     }
 }
 
+// `test file...` runs the `deftest`s of each module file and exits with the overall status
+fn test_command(paths: &[String]) -> bool {
+    let mut ok = true;
+    for path in paths {
+        let name = Path::new(path).file_stem().unwrap_or_default();
+        let results = std::fs::read_to_string(path)
+            .map_err(|err| format!("Cannot read {path}: {err}\n"))
+            .and_then(|source| run_tests(&name.to_string_lossy(), &source));
+        match results {
+            Ok(results) => {
+                print!("{}", report(path, &results));
+                ok &= passed(&results);
+            }
+            Err(err) => {
+                eprint!("{path}: {err}");
+                ok = false;
+            }
+        }
+    }
+    ok
+}
+
 fn main() -> eframe::Result {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let [cmd, paths @ ..] = &args[..]
+        && cmd == "test"
+    {
+        std::process::exit(if test_command(paths) { 0 } else { 1 });
+    }
     eframe::run_native(
         "App1",
         eframe::NativeOptions {
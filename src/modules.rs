@@ -0,0 +1,407 @@
+use crate::token_tree::TokenTree;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::rc::Rc;
+use std::time::SystemTime;
+
+const EXTENSION: &str = "lisp";
+
+pub const PRELUDE: &str = include_str!("prelude.lisp");
+
+// `(module item ...)` where each item is `(def name value)`, `(deftest name body)`
+// or `(import name [as alias])`
+struct Module {
+    items: Vec<Item>,
+}
+
+enum Item {
+    Import(Import),
+    Def(Rc<str>, TokenTree),
+    Test(Rc<str>, TokenTree),
+}
+
+struct Import {
+    name: Rc<str>,
+    alias: Rc<str>,
+}
+
+// A `deftest` as a program of its own, `index` being its position among the module items
+pub struct Test {
+    pub name: Rc<str>,
+    pub index: usize,
+    pub program: TokenTree,
+}
+
+thread_local! {
+    static SEARCH_PATH: RefCell<Vec<PathBuf>> = RefCell::new(default_search_path());
+    // Parsed modules by file, reloaded when the file changes
+    static CACHE: RefCell<HashMap<PathBuf, (SystemTime, Rc<Module>)>> = RefCell::default();
+    static CURRENT_PRELUDE: RefCell<Option<Rc<Module>>> = RefCell::new(Some(Rc::new(
+        parse_source("prelude", PRELUDE).expect("the bundled prelude is a valid module")
+    )));
+}
+
+// Directories listed in `INTERP_PATH`, or the working directory
+fn default_search_path() -> Vec<PathBuf> {
+    match std::env::var_os("INTERP_PATH") {
+        Some(x) => std::env::split_paths(&x).collect(),
+        None => vec![PathBuf::from(".")],
+    }
+}
+
+pub fn set_search_path(dirs: Vec<PathBuf>) {
+    SEARCH_PATH.set(dirs);
+}
+
+// `None` opts out of the prelude, otherwise the source replaces the bundled one
+pub fn set_prelude(source: Option<&str>) -> Result<(), String> {
+    let m = match source {
+        Some(x) => Some(Rc::new(parse_source("prelude", x)?)),
+        None => None,
+    };
+    CURRENT_PRELUDE.set(m);
+    Ok(())
+}
+
+fn parse_source(name: &str, source: &str) -> Result<Module, String> {
+    let tree: TokenTree = source.parse()?;
+    let mut error_log = String::new();
+    parse_module(&mut error_log, name, &tree).ok_or(error_log)
+}
+
+fn collect_atoms(tree: &TokenTree, out: &mut HashSet<Rc<str>>) {
+    match tree {
+        TokenTree::Atom(x) => _ = out.insert(x.clone()),
+        TokenTree::Array(items) => items.iter().for_each(|x| collect_atoms(x, out)),
+        TokenTree::Int64(_) | TokenTree::Str(_) => {}
+    }
+}
+
+// Drops the bindings nothing refers to, so that unused library code is not even checked
+fn used_bindings(
+    bindings: Vec<(Rc<str>, TokenTree)>,
+    body: &TokenTree,
+) -> Vec<(Rc<str>, TokenTree)> {
+    let mut used = HashSet::new();
+    collect_atoms(body, &mut used);
+    let mut out = Vec::new();
+    for (name, val) in bindings.into_iter().rev() {
+        if used.contains(&name) {
+            collect_atoms(&val, &mut used);
+            out.push((name, val));
+        }
+    }
+    out.reverse();
+    out
+}
+
+impl Module {
+    fn defs(&self) -> impl Iterator<Item = &Rc<str>> {
+        self.items.iter().filter_map(|x| match x {
+            Item::Def(name, _) => Some(name),
+            Item::Import(_) | Item::Test(_, _) => None,
+        })
+    }
+}
+
+fn qualified(module: &str, name: &str) -> Rc<str> {
+    format!("{module}/{name}").into()
+}
+
+// `(let name value body)` for each binding, the first one outermost
+fn wrap_lets(bindings: Vec<(Rc<str>, TokenTree)>, body: TokenTree) -> TokenTree {
+    bindings.into_iter().rev().fold(body, |body, (name, val)| {
+        TokenTree::Array(vec![
+            TokenTree::Atom("let".into()),
+            TokenTree::Atom(name),
+            val,
+            body,
+        ])
+    })
+}
+
+// Parses the `name [as alias]` part of an import, returning the items after it
+fn parse_import(items: &[TokenTree]) -> Option<(Import, &[TokenTree])> {
+    let TokenTree::Atom(name) = items.first()? else {
+        return None;
+    };
+    match items {
+        [_, TokenTree::Atom(kw), TokenTree::Atom(alias), rest @ ..] if &kw[..] == "as" => {
+            let import = Import {
+                name: name.clone(),
+                alias: alias.clone(),
+            };
+            Some((import, rest))
+        }
+        [_, rest @ ..] => {
+            let import = Import {
+                name: name.clone(),
+                alias: name.clone(),
+            };
+            Some((import, rest))
+        }
+        [] => None,
+    }
+}
+
+fn has_head<'t>(tree: &'t TokenTree, head: &str) -> Option<&'t [TokenTree]> {
+    match tree {
+        TokenTree::Array(items) => match items.first() {
+            Some(TokenTree::Atom(x)) if &x[..] == head => Some(&items[1..]),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn parse_item(error_log: &mut String, name: &str, tree: &TokenTree) -> Option<Item> {
+    if let Some(args) = has_head(tree, "import")
+        && let Some((import, [])) = parse_import(args)
+    {
+        return Some(Item::Import(import));
+    }
+    if let Some([TokenTree::Atom(x), val]) = has_head(tree, "def") {
+        return Some(Item::Def(x.clone(), val.clone()));
+    }
+    if let Some([TokenTree::Atom(x), body]) = has_head(tree, "deftest") {
+        return Some(Item::Test(x.clone(), body.clone()));
+    }
+    writeln!(
+        error_log,
+        "Expected `(def name value)`, `(deftest name body)` or `(import module)` \
+         in module `{name}`, found `{tree}`"
+    )
+    .unwrap();
+    None
+}
+
+fn parse_module(error_log: &mut String, name: &str, tree: &TokenTree) -> Option<Module> {
+    let Some(items) = has_head(tree, "module") else {
+        writeln!(error_log, "Module `{name}` is not a `(module ...)` form").unwrap();
+        return None;
+    };
+    let mut out_opt = Some(Vec::new());
+    let mut names = HashSet::new();
+    let mut tests = HashSet::new();
+    for it in items {
+        let item_opt = parse_item(error_log, name, it);
+        let duplicate = match &item_opt {
+            Some(Item::Def(x, _)) if !names.insert(x.clone()) => Some(("definition", x)),
+            Some(Item::Test(x, _)) if !tests.insert(x.clone()) => Some(("test", x)),
+            _ => None,
+        };
+        if let Some((kind, x)) = duplicate {
+            writeln!(error_log, "Duplicate {kind} `{x}` in module `{name}`").unwrap();
+            out_opt = None;
+        }
+        match (&mut out_opt, item_opt) {
+            (Some(out), Some(item)) => out.push(item),
+            _ => out_opt = None,
+        }
+    }
+    Some(Module { items: out_opt? })
+}
+
+#[derive(Default)]
+struct ResolveContext {
+    error_log: String,
+    loaded: HashMap<Rc<str>, Rc<Module>>,
+    // Modules whose imports are being resolved, innermost last
+    loading: Vec<Rc<str>>,
+    // Definitions of every loaded module under their qualified names, in dependency order
+    bindings: Vec<(Rc<str>, TokenTree)>,
+}
+
+impl ResolveContext {
+    fn find(&mut self, name: &str) -> Option<PathBuf> {
+        let segments: Vec<_> = name.split('.').collect();
+        if segments
+            .iter()
+            .any(|x| x.is_empty() || x.contains(['/', '\\']))
+        {
+            writeln!(&mut self.error_log, "Invalid module name `{name}`").unwrap();
+            return None;
+        }
+        let mut rel: PathBuf = segments.iter().collect();
+        rel.set_extension(EXTENSION);
+        let dirs = SEARCH_PATH.with_borrow(|x| x.clone());
+        let found = dirs.iter().map(|x| x.join(&rel)).find(|x| x.is_file());
+        if found.is_none() {
+            let dirs: Vec<_> = dirs.iter().map(|x| x.display().to_string()).collect();
+            writeln!(
+                &mut self.error_log,
+                "Cannot find module `{name}`: no {} in {}",
+                rel.display(),
+                dirs.join(", ")
+            )
+            .unwrap();
+        }
+        found
+    }
+
+    fn load(&mut self, name: &str) -> Option<Rc<Module>> {
+        let path = self.find(name)?;
+        let modified = path.metadata().and_then(|x| x.modified()).ok();
+        let cached = CACHE.with_borrow(|cache| match (cache.get(&path), modified) {
+            (Some((time, m)), Some(modified)) if *time == modified => Some(m.clone()),
+            _ => None,
+        });
+        if cached.is_some() {
+            return cached;
+        }
+        let text = match std::fs::read_to_string(&path) {
+            Ok(x) => x,
+            Err(err) => {
+                let path = path.display();
+                writeln!(
+                    &mut self.error_log,
+                    "Cannot read module `{name}` from {path}: {err}"
+                )
+                .unwrap();
+                return None;
+            }
+        };
+        let tree = match text.parse::<TokenTree>() {
+            Ok(x) => x,
+            Err(err) => {
+                let path = path.display();
+                write!(&mut self.error_log, "In module `{name}` ({path}):\n{err}").unwrap();
+                return None;
+            }
+        };
+        let m = Rc::new(parse_module(&mut self.error_log, name, &tree)?);
+        if let Some(modified) = modified {
+            CACHE.with_borrow_mut(|cache| cache.insert(path, (modified, m.clone())));
+        }
+        Some(m)
+    }
+
+    // Binds the definitions of the module and everything it imports, each module once
+    fn import(&mut self, name: &Rc<str>) -> Option<Rc<Module>> {
+        if let Some(m) = self.loaded.get(name) {
+            return Some(m.clone());
+        }
+        if let Some(pos) = self.loading.iter().position(|x| x == name) {
+            let cycle: Vec<_> = self.loading[pos..].iter().map(|x| &x[..]).collect();
+            writeln!(
+                &mut self.error_log,
+                "Import cycle: {} -> {name}",
+                cycle.join(" -> ")
+            )
+            .unwrap();
+            return None;
+        }
+        self.loading.push(name.clone());
+        let m_opt = self.load(name);
+        // Only the tests of the module under test are run
+        let out = m_opt.and_then(|m| self.bind_module(name, &m).map(|_| m));
+        self.loading.pop();
+        let m = out?;
+        self.loaded.insert(name.clone(), m.clone());
+        Some(m)
+    }
+
+    // Each definition and test only sees the definitions and imports above it in its own
+    // module; returns the tests with those names bound
+    fn bind_module(&mut self, name: &str, m: &Module) -> Option<Vec<Test>> {
+        let mut scope = Vec::new();
+        let mut tests = Vec::new();
+        let mut ok = true;
+        for (index, it) in m.items.iter().enumerate() {
+            match it {
+                Item::Import(import) => match self.import(&import.name) {
+                    Some(dep) => scope.extend(aliases(import, &dep)),
+                    None => ok = false,
+                },
+                Item::Def(x, val) => {
+                    let full = qualified(name, x);
+                    self.bindings.push((full.clone(), in_scope(&scope, val)));
+                    scope.push((x.clone(), TokenTree::Atom(full)));
+                }
+                Item::Test(x, body) => tests.push(Test {
+                    name: x.clone(),
+                    index,
+                    program: in_scope(&scope, body),
+                }),
+            }
+        }
+        ok.then_some(tests)
+    }
+}
+
+fn in_scope(scope: &[(Rc<str>, TokenTree)], val: &TokenTree) -> TokenTree {
+    let mut atoms = HashSet::new();
+    collect_atoms(val, &mut atoms);
+    let visible = scope.iter().filter(|x| atoms.contains(&x.0)).cloned();
+    wrap_lets(visible.collect(), val.clone())
+}
+
+// Bindings that make the definitions visible under the alias of the import
+fn aliases(import: &Import, m: &Module) -> Vec<(Rc<str>, TokenTree)> {
+    if import.alias == import.name {
+        return Vec::new();
+    }
+    let names = m.defs().map(|x| {
+        let full = TokenTree::Atom(qualified(&import.name, x));
+        (qualified(&import.alias, x), full)
+    });
+    names.collect()
+}
+
+// Replaces the `(import name [as alias] body)` forms at the top of the program
+// with `let`s binding the qualified names `alias/definition`, after the prelude
+pub fn resolve(tree: &TokenTree) -> Result<TokenTree, String> {
+    let mut ctx = ResolveContext::default();
+    // The prelude is bound first and its definitions are also visible unqualified
+    if let Some(m) = CURRENT_PRELUDE.with_borrow(|x| x.clone()) {
+        let name: Rc<str> = "prelude".into();
+        ctx.bind_module(&name, &m);
+        let unqualified = m
+            .defs()
+            .map(|x| (x.clone(), TokenTree::Atom(qualified(&name, x))));
+        let unqualified: Vec<_> = unqualified.collect();
+        ctx.bindings.extend(unqualified);
+        ctx.loaded.insert(name, m);
+    }
+    let mut body = tree;
+    let mut scope = Vec::new();
+    while let Some(args) = has_head(body, "import") {
+        let Some((import, [rest])) = parse_import(args) else {
+            return Err(format!(
+                "Expected `(import module [as alias] body)`, found `{body}`\n"
+            ));
+        };
+        body = rest;
+        if let Some(m) = ctx.import(&import.name) {
+            scope.extend(aliases(&import, &m));
+        }
+    }
+    if !ctx.error_log.is_empty() {
+        return Err(ctx.error_log);
+    }
+    let mut bindings = ctx.bindings;
+    bindings.extend(scope);
+    Ok(wrap_lets(used_bindings(bindings, body), body.clone()))
+}
+
+// The `deftest`s of a module source, each with the definitions above it bound like in
+// `resolve`; the prelude is bound when the programs themselves are resolved
+pub fn tests(name: &str, source: &str) -> Result<Vec<Test>, String> {
+    let m = parse_source(name, source)?;
+    let mut ctx = ResolveContext::default();
+    // Imports of the module under test back into itself are cycles
+    ctx.loading.push(name.into());
+    let tests_opt = ctx.bind_module(name, &m);
+    match tests_opt {
+        Some(tests) if ctx.error_log.is_empty() => {
+            let tests = tests.into_iter().map(|x| Test {
+                program: wrap_lets(used_bindings(ctx.bindings.clone(), &x.program), x.program),
+                ..x
+            });
+            Ok(tests.collect())
+        }
+        _ => Err(ctx.error_log),
+    }
+}
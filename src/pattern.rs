@@ -0,0 +1,247 @@
+use crate::typed_tree::TypeInfo;
+use std::fmt::{self, Display};
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub enum TypedPattern {
+    Wildcard,
+    Bind(Rc<str>),
+    Int64(i64),
+    Variant(usize, Vec<TypedPattern>),
+    Tuple(Vec<TypedPattern>),
+}
+
+// A value not covered by any arm, reported back to the user
+#[derive(Debug, Clone)]
+pub enum Witness {
+    Wildcard,
+    Int64(i64),
+    Variant(Rc<str>, Vec<Witness>),
+    Tuple(Vec<Witness>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum Ctor {
+    Int64(i64),
+    Variant(usize),
+    Tuple,
+}
+
+impl Display for Witness {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wildcard => write!(f, "_"),
+            Self::Int64(x) => write!(f, "{x}"),
+            Self::Variant(name, payload) => {
+                write!(f, "({name}")?;
+                for it in payload {
+                    write!(f, " {it}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Tuple(items) => {
+                write!(f, "(tuple")?;
+                for it in items {
+                    write!(f, " {it}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl TypedPattern {
+    fn ctor(&self) -> Option<Ctor> {
+        match self {
+            Self::Wildcard | Self::Bind(_) => None,
+            &Self::Int64(x) => Some(Ctor::Int64(x)),
+            Self::Variant(i, _) => Some(Ctor::Variant(*i)),
+            Self::Tuple(_) => Some(Ctor::Tuple),
+        }
+    }
+}
+
+fn ctor_fields(typ: &TypeInfo, ctor: Ctor) -> Vec<TypeInfo> {
+    match (typ, ctor) {
+        (TypeInfo::Enum(info), Ctor::Variant(i)) => info.variants[i].1.clone(),
+        (TypeInfo::Tuple(items), Ctor::Tuple) => items.clone(),
+        _ => Vec::new(),
+    }
+}
+
+// Every constructor of the type, or `None` when they cannot be enumerated
+fn all_ctors(typ: &TypeInfo) -> Option<Vec<Ctor>> {
+    match typ {
+        TypeInfo::Enum(info) => Some((0..info.variants.len()).map(Ctor::Variant).collect()),
+        TypeInfo::Tuple(_) => Some(vec![Ctor::Tuple]),
+        _ => None,
+    }
+}
+
+fn first_column_ctors(rows: &[Vec<&TypedPattern>]) -> Vec<Ctor> {
+    let mut out = Vec::new();
+    for row in rows {
+        if let Some(ctor) = row[0].ctor()
+            && !out.contains(&ctor)
+        {
+            out.push(ctor);
+        }
+    }
+    out
+}
+
+fn specialize<'p>(
+    rows: &[Vec<&'p TypedPattern>],
+    ctor: Ctor,
+    arity: usize,
+) -> Vec<Vec<&'p TypedPattern>> {
+    let mut out = Vec::new();
+    for row in rows {
+        let mut new_row = Vec::new();
+        match row[0] {
+            TypedPattern::Wildcard | TypedPattern::Bind(_) => {
+                new_row.extend((0..arity).map(|_| &TypedPattern::Wildcard));
+            }
+            TypedPattern::Variant(i, payload) if Ctor::Variant(*i) == ctor => {
+                new_row.extend(payload.iter());
+            }
+            &TypedPattern::Int64(x) if Ctor::Int64(x) == ctor => {}
+            TypedPattern::Tuple(items) if Ctor::Tuple == ctor => {
+                new_row.extend(items.iter());
+            }
+            _ => continue,
+        }
+        new_row.extend(row[1..].iter().copied());
+        out.push(new_row);
+    }
+    out
+}
+
+fn default_rows<'p>(rows: &[Vec<&'p TypedPattern>]) -> Vec<Vec<&'p TypedPattern>> {
+    rows.iter()
+        .filter(|row| row[0].ctor().is_none())
+        .map(|row| row[1..].to_vec())
+        .collect()
+}
+
+fn is_useful(rows: &[Vec<&TypedPattern>], row: &[&TypedPattern], types: &[TypeInfo]) -> bool {
+    if types.is_empty() {
+        return rows.is_empty();
+    }
+    if let Some(ctor) = row[0].ctor() {
+        let fields = ctor_fields(&types[0], ctor);
+        let rows1 = specialize(rows, ctor, fields.len());
+        let row1 = specialize(&[row.to_vec()], ctor, fields.len()).remove(0);
+        let types1 = [&fields[..], &types[1..]].concat();
+        return is_useful(&rows1, &row1, &types1);
+    }
+    let used = first_column_ctors(rows);
+    match all_ctors(&types[0]) {
+        Some(all) if all.iter().all(|x| used.contains(x)) => all.into_iter().any(|ctor| {
+            let fields = ctor_fields(&types[0], ctor);
+            let rows1 = specialize(rows, ctor, fields.len());
+            let row1 = specialize(&[row.to_vec()], ctor, fields.len()).remove(0);
+            let types1 = [&fields[..], &types[1..]].concat();
+            is_useful(&rows1, &row1, &types1)
+        }),
+        _ => is_useful(&default_rows(rows), &row[1..], &types[1..]),
+    }
+}
+
+fn witness_for(typ: &TypeInfo, ctor: Ctor, payload: Vec<Witness>) -> Witness {
+    match (typ, ctor) {
+        (TypeInfo::Enum(info), Ctor::Variant(i)) => {
+            Witness::Variant(info.variants[i].0.clone(), payload)
+        }
+        (_, Ctor::Int64(x)) => Witness::Int64(x),
+        (_, Ctor::Tuple) => Witness::Tuple(payload),
+        _ => Witness::Wildcard,
+    }
+}
+
+fn missing_with_ctor(
+    rows: &[Vec<&TypedPattern>],
+    types: &[TypeInfo],
+    ctor: Ctor,
+) -> Vec<Vec<Witness>> {
+    let fields = ctor_fields(&types[0], ctor);
+    let rows1 = specialize(rows, ctor, fields.len());
+    let types1 = [&fields[..], &types[1..]].concat();
+    let mut out = Vec::new();
+    for mut it in missing(&rows1, &types1) {
+        let tail = it.split_off(fields.len());
+        let mut row = vec![witness_for(&types[0], ctor, it)];
+        row.extend(tail);
+        out.push(row);
+    }
+    out
+}
+
+// Unlike a plain usefulness check this lists every uncovered case, so that
+// all missing variants get reported at once
+fn missing(rows: &[Vec<&TypedPattern>], types: &[TypeInfo]) -> Vec<Vec<Witness>> {
+    if types.is_empty() {
+        return if rows.is_empty() {
+            vec![Vec::new()]
+        } else {
+            Vec::new()
+        };
+    }
+    let used = first_column_ctors(rows);
+    let all = all_ctors(&types[0]);
+    let mut out = Vec::new();
+    match all {
+        Some(all) if all.iter().all(|x| used.contains(x)) => {
+            for ctor in all {
+                out.extend(missing_with_ctor(rows, types, ctor));
+            }
+        }
+        _ => {
+            for &ctor in &used {
+                out.extend(missing_with_ctor(rows, types, ctor));
+            }
+            let tails = missing(&default_rows(rows), &types[1..]);
+            let heads = match all {
+                Some(all) if !used.is_empty() => all
+                    .into_iter()
+                    .filter(|x| !used.contains(x))
+                    .map(|ctor| {
+                        let arity = ctor_fields(&types[0], ctor).len();
+                        witness_for(&types[0], ctor, vec![Witness::Wildcard; arity])
+                    })
+                    .collect(),
+                _ => vec![Witness::Wildcard],
+            };
+            for tail in &tails {
+                for head in &heads {
+                    let mut row = vec![head.clone()];
+                    row.extend(tail.iter().cloned());
+                    out.push(row);
+                }
+            }
+        }
+    }
+    out
+}
+
+// Indices of arms that can never be reached because earlier arms cover them
+pub fn redundant_arms(patterns: &[&TypedPattern], typ: &TypeInfo) -> Vec<usize> {
+    let types = [typ.clone()];
+    let mut rows = Vec::new();
+    let mut out = Vec::new();
+    for (i, &pattern) in patterns.iter().enumerate() {
+        if !is_useful(&rows, &[pattern], &types) {
+            out.push(i);
+        }
+        rows.push(vec![pattern]);
+    }
+    out
+}
+
+pub fn missing_patterns(patterns: &[&TypedPattern], typ: &TypeInfo) -> Vec<Witness> {
+    let rows: Vec<_> = patterns.iter().map(|&x| vec![x]).collect();
+    missing(&rows, std::slice::from_ref(typ))
+        .into_iter()
+        .map(|mut x| x.remove(0))
+        .collect()
+}
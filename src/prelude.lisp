@@ -0,0 +1,48 @@
+(module
+  (def min (fn (x y) (array-get (array-sort (array x y)) 0)))
+  (def max (fn (x y) (array-get (array-sort (array x y)) 1)))
+  (def abs (fn (x) (max x (- 0 x))))
+  (def sign (fn (x) (match x (0 0) (_ (/ x (abs x))))))
+  (def clamp (fn (x lo hi) (max lo (min x hi))))
+  (def pow (letrec pow (fn (x n) (match n (0 1) (_ (* x (pow x (- n 1)))))) pow))
+  (def gcd (letrec gcd (fn (a b) (match b (0 (abs a)) (_ (gcd b (% a b))))) gcd))
+
+  (def sum (fn (xs) (fold (fn (acc x) (+ acc x)) 0 xs)))
+  (def product (fn (xs) (fold (fn (acc x) (* acc x)) 1 xs)))
+  (def first (fn (xs) (array-get xs 0)))
+  (def last (fn (xs) (array-get xs (- (array-len xs) 1))))
+  (def array-min (fn (xs) (first (array-sort xs))))
+  (def array-max (fn (xs) (last (array-sort xs))))
+  (def contains (fn (xs y) (any (fn (x) (match (- x y) (0 1) (_ 0))) xs)))
+  (def count (fn (f xs) (array-len (filter f xs))))
+  (def take (fn (xs n) (array-slice xs 0 (clamp n 0 (array-len xs)))))
+  (def drop (fn (xs n) (array-slice xs (clamp n 0 (array-len xs)) (array-len xs))))
+
+  (def some (generic (T) (fn ((x T)) (variant (Option T) Some x))))
+  (def some? (generic (T) (fn ((o (Option T))) (match o ((Some _) 1) ((None) 0)))))
+  (def none? (generic (T) (fn ((o (Option T))) (match o ((Some _) 0) ((None) 1)))))
+  (def unwrap-or (generic (T) (fn ((o (Option T)) (d T)) (match o ((Some x) x) ((None) d)))))
+  (def option-map
+    (generic (A B)
+      (fn ((f (fn-t (A) B)) (o (Option A)))
+        (match o
+          ((Some x) (variant (Option B) Some (f x)))
+          ((None) (variant (Option B) None))))))
+
+  (def ok (generic (T E) (fn ((x T)) (variant (Result T E) Ok x))))
+  (def err (generic (T E) (fn ((e E)) (variant (Result T E) Err e))))
+  (def ok? (generic (T E) (fn ((r (Result T E))) (match r ((Ok _) 1) ((Err _) 0)))))
+  (def err? (generic (T E) (fn ((r (Result T E))) (match r ((Ok _) 0) ((Err _) 1)))))
+  (def result-or (generic (T E) (fn ((r (Result T E)) (d T)) (match r ((Ok x) x) ((Err _) d)))))
+  (def result-map
+    (generic (T U E)
+      (fn ((f (fn-t (T) U)) (r (Result T E)))
+        (match r
+          ((Ok x) (variant (Result U E) Ok (f x)))
+          ((Err e) (variant (Result U E) Err e))))))
+  (def ok-or
+    (generic (T E)
+      (fn ((o (Option T)) (e E))
+        (match o
+          ((Some x) (variant (Result T E) Ok x))
+          ((None) (variant (Result T E) Err e)))))))
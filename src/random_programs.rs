@@ -0,0 +1,806 @@
+// Property tests on random programs: well-typed ones built from a target type, single
+// mutations of them that must be rejected, and token soup that must never make a stage panic
+use crate::syntax_tree::{
+    ArithmeticOp, ArrayOp, Catch, MapOp, MapUpdate, Pattern, Place, SyntaxTree,
+};
+use crate::token_tree::{TokenTree, item_spans};
+use crate::typed_tree::{TypeInfo, TypedTree, has_type, interpret_no_context, option_type};
+use std::fmt::Debug;
+use std::panic::{AssertUnwindSafe, catch_unwind};
+use std::rc::Rc;
+
+const PROGRAMS: u64 = 400;
+const MUTATIONS_PER_PROGRAM: usize = 3;
+const SOUPS: u64 = 3000;
+
+// SplitMix64, so that a failure is reproduced from its seed alone
+#[derive(Default)]
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn percent(&mut self, p: usize) -> bool {
+        self.below(100) < p
+    }
+
+    fn int(&mut self) -> i64 {
+        self.below(201) as i64 - 100
+    }
+}
+
+const OPS: &[ArithmeticOp] = &[
+    ArithmeticOp::Add,
+    ArithmeticOp::Sub,
+    ArithmeticOp::Mul,
+    ArithmeticOp::Div,
+    ArithmeticOp::Rem,
+];
+
+fn ident(x: &str) -> SyntaxTree {
+    SyntaxTree::Ident(x.into())
+}
+
+// The source expression denoting the type
+fn type_expr(t: &TypeInfo) -> SyntaxTree {
+    match t {
+        TypeInfo::Int64 => ident("i64"),
+        TypeInfo::Str => ident("str"),
+        TypeInfo::Array(t, len) => SyntaxTree::LiteralArrayType(Box::new(type_expr(t)), *len),
+        TypeInfo::Tuple(items) => {
+            SyntaxTree::LiteralTupleType(items.iter().map(type_expr).collect())
+        }
+        TypeInfo::Func(params, ret) => SyntaxTree::LiteralFuncType(
+            params.iter().map(type_expr).collect(),
+            Box::new(type_expr(ret)),
+        ),
+        TypeInfo::Enum(e) => {
+            SyntaxTree::Call(Box::new(ident("Option")), vec![type_expr(&e.params[0])])
+        }
+        _ => unreachable!("not generated: {t}"),
+    }
+}
+
+// `TypeInfo` compares nominal types by id, which is the same for every `Option`
+fn same_type(x: &TypeInfo, y: &TypeInfo) -> bool {
+    x.to_string() == y.to_string()
+}
+
+fn form(name: &str, args: Vec<SyntaxTree>) -> SyntaxTree {
+    SyntaxTree::Form(crate::forms::lookup(name).unwrap(), args)
+}
+
+fn field(record: &Rc<str>, name: &str) -> Place {
+    Place::Field(Box::new(Place::Var(record.clone())), name.into())
+}
+
+fn field_get(record: &Rc<str>, name: &str) -> SyntaxTree {
+    SyntaxTree::FieldGet(Box::new(SyntaxTree::Ident(record.clone())), name.into())
+}
+
+fn option_payload(t: &TypeInfo) -> Option<&TypeInfo> {
+    match t {
+        TypeInfo::Enum(e) if e.variants[0].0.as_ref() == "None" => Some(&e.params[0]),
+        _ => None,
+    }
+}
+
+#[derive(Default)]
+struct Generator {
+    rng: Rng,
+    // Variables in scope, innermost last
+    scope: Vec<(Rc<str>, TypeInfo)>,
+    // Return types of the enclosing functions, innermost last
+    returns: Vec<TypeInfo>,
+    var_count: usize,
+    // Places where a single change makes the program ill-typed, counted in generation order
+    sites: usize,
+    mutate_at: Option<usize>,
+}
+
+impl Generator {
+    fn new(seed: u64, mutate_at: Option<usize>) -> Self {
+        Self {
+            rng: Rng(seed),
+            mutate_at,
+            ..Default::default()
+        }
+    }
+
+    // Whether the node about to be built at the next site gets mutated
+    fn site(&mut self) -> bool {
+        self.sites += 1;
+        self.mutate_at == Some(self.sites - 1)
+    }
+
+    fn fresh(&mut self) -> Rc<str> {
+        self.var_count += 1;
+        format!("v{}", self.var_count).into()
+    }
+
+    fn random_type(&mut self, depth: usize) -> TypeInfo {
+        if depth == 0 {
+            return [TypeInfo::Int64, TypeInfo::Str][self.rng.below(2)].clone();
+        }
+        match self.rng.below(10) {
+            0..=3 => TypeInfo::Int64,
+            4 => TypeInfo::Str,
+            5 => {
+                let len = self.rng.percent(30).then(|| 1 + self.rng.below(3));
+                TypeInfo::Array(Box::new(self.random_type(depth - 1)), len)
+            }
+            6 => {
+                let len = 2 + self.rng.below(2);
+                TypeInfo::Tuple((0..len).map(|_| self.random_type(depth - 1)).collect())
+            }
+            7 => {
+                let params = (0..1 + self.rng.below(2)).map(|_| self.random_type(depth - 1));
+                let params = params.collect();
+                TypeInfo::Func(params, Box::new(self.random_type(depth - 1)))
+            }
+            _ => option_type(self.random_type(depth - 1)),
+        }
+    }
+
+    // Types a `var` can hold, which start out as their zero value
+    fn zeroable_type(&mut self, depth: usize) -> TypeInfo {
+        loop {
+            let t = self.random_type(depth);
+            if t.has_zero() {
+                return t;
+            }
+        }
+    }
+
+    fn string(&mut self) -> Rc<str> {
+        const CHARS: &[char] = &[
+            'a', 'b', ' ', '"', '\\', '\n', '\t', '\r', '(', ';', 'é', '\u{1}',
+        ];
+        let len = self.rng.below(5);
+        (0..len)
+            .map(|_| CHARS[self.rng.below(CHARS.len())])
+            .collect::<String>()
+            .into()
+    }
+
+    fn with_var<T>(&mut self, name: Rc<str>, t: TypeInfo, f: impl FnOnce(&mut Self) -> T) -> T {
+        self.scope.push((name, t));
+        let out = f(self);
+        self.scope.pop();
+        out
+    }
+
+    fn variable(&mut self, t: &TypeInfo) -> Option<SyntaxTree> {
+        let found: Vec<_> = self.scope.iter().filter(|x| same_type(&x.1, t)).collect();
+        if found.is_empty() {
+            return None;
+        }
+        let name = found[self.rng.below(found.len())].0.clone();
+        if self.site() {
+            return Some(ident("unbound"));
+        }
+        Some(SyntaxTree::Ident(name))
+    }
+
+    fn lambda(&mut self, params: &[TypeInfo], ret: &TypeInfo, depth: usize) -> SyntaxTree {
+        let names: Vec<_> = params.iter().map(|_| self.fresh()).collect();
+        let scope_len = self.scope.len();
+        self.scope
+            .extend(names.iter().cloned().zip(params.iter().cloned()));
+        self.returns.push(ret.clone());
+        let body = self.expr(ret, depth);
+        self.returns.pop();
+        self.scope.truncate(scope_len);
+        let params = names
+            .into_iter()
+            .zip(params.iter().map(|x| Some(type_expr(x))));
+        SyntaxTree::Lambda(params.collect(), Box::new(body))
+    }
+
+    // Smallest expressions of the type, used when out of depth
+    fn leaf(&mut self, t: &TypeInfo) -> SyntaxTree {
+        if self.rng.percent(50)
+            && let Some(x) = self.variable(t)
+        {
+            return x;
+        }
+        match t {
+            TypeInfo::Int64 => SyntaxTree::LiteralInt64(self.rng.int()),
+            TypeInfo::Str => SyntaxTree::LiteralStr(self.string()),
+            TypeInfo::Array(inner, Some(len)) => {
+                SyntaxTree::LiteralArray((0..*len).map(|_| self.leaf(inner)).collect())
+            }
+            TypeInfo::Array(inner, None) => {
+                let len = self.rng.below(3);
+                let items = (0..len).map(|_| self.leaf(inner)).collect();
+                self.unsized_array(t, items)
+            }
+            TypeInfo::Tuple(items) => {
+                SyntaxTree::LiteralTuple(items.iter().map(|x| self.leaf(x)).collect())
+            }
+            TypeInfo::Func(params, ret) => self.lambda(params, ret, 0),
+            _ => {
+                let payload = option_payload(t).unwrap().clone();
+                if self.rng.percent(30) {
+                    return SyntaxTree::LiteralVariant(
+                        Box::new(type_expr(t)),
+                        "None".into(),
+                        vec![],
+                    );
+                }
+                let x = self.leaf(&payload);
+                self.variant_some(t, x)
+            }
+        }
+    }
+
+    // Array literals have their length in their type, which differs between branches unless
+    // it is dropped by passing the array through a parameter of the unsized type
+    fn unsized_array(&mut self, t: &TypeInfo, items: Vec<SyntaxTree>) -> SyntaxTree {
+        let name = self.fresh();
+        let id = SyntaxTree::Lambda(
+            vec![(name.clone(), Some(type_expr(t)))],
+            Box::new(SyntaxTree::Ident(name)),
+        );
+        SyntaxTree::Call(Box::new(id), vec![SyntaxTree::LiteralArray(items)])
+    }
+
+    fn variant_some(&mut self, t: &TypeInfo, x: SyntaxTree) -> SyntaxTree {
+        let name = if self.site() { "Sum" } else { "Some" };
+        SyntaxTree::LiteralVariant(Box::new(type_expr(t)), name.into(), vec![x])
+    }
+
+    fn expr(&mut self, t: &TypeInfo, depth: usize) -> SyntaxTree {
+        if depth == 0 || self.rng.percent(15) {
+            return self.leaf(t);
+        }
+        let depth = depth - 1;
+        match self.rng.below(16) {
+            0 => {
+                let u = self.random_type(2);
+                let val = self.expr(&u, depth);
+                let name = self.fresh();
+                let body = self.with_var(name.clone(), u, |g| g.expr(t, depth));
+                SyntaxTree::LetVal(name, Box::new(val), Box::new(body))
+            }
+            1 => {
+                let scrutinee = self.expr(&TypeInfo::Int64, depth);
+                let mut arms = vec![(Pattern::LiteralInt64(self.rng.int()), self.expr(t, depth))];
+                let name = self.fresh();
+                let last = self.with_var(name.clone(), TypeInfo::Int64, |g| g.expr(t, depth));
+                arms.push((Pattern::Bind(name), last));
+                SyntaxTree::Match(Box::new(scrutinee), arms)
+            }
+            2 => {
+                let u = self.random_type(2);
+                let opt = option_type(u.clone());
+                let scrutinee = self.expr(&opt, depth);
+                let name = self.fresh();
+                let some = self.with_var(name.clone(), u, |g| g.expr(t, depth));
+                let arms = vec![
+                    (
+                        Pattern::Variant("Some".into(), vec![Pattern::Bind(name)]),
+                        some,
+                    ),
+                    (Pattern::Variant("None".into(), vec![]), self.expr(t, depth)),
+                ];
+                SyntaxTree::Match(Box::new(scrutinee), arms)
+            }
+            3 => {
+                let params: Vec<_> = (0..1 + self.rng.below(2))
+                    .map(|_| self.random_type(2))
+                    .collect();
+                let func = self.lambda(&params, t, depth);
+                let mut args: Vec<_> = params.iter().map(|x| self.expr(x, depth)).collect();
+                if self.site() {
+                    args.push(args[0].clone());
+                }
+                SyntaxTree::Call(Box::new(func), args)
+            }
+            4 => {
+                let mut items: Vec<_> = (0..self.rng.below(3))
+                    .map(|_| self.random_type(1))
+                    .collect();
+                let index = self.rng.below(items.len() + 1);
+                items.insert(index, t.clone());
+                let vals = items.iter().map(|x| self.expr(x, depth)).collect();
+                let index = if self.site() { items.len() } else { index };
+                SyntaxTree::TupleGet(Box::new(SyntaxTree::LiteralTuple(vals)), index)
+            }
+            5 => {
+                let len = 1 + self.rng.below(3);
+                let items = (0..len).map(|_| self.expr(t, depth)).collect();
+                let index = SyntaxTree::LiteralInt64(self.rng.below(len) as i64);
+                SyntaxTree::ArrayGet(Box::new(SyntaxTree::LiteralArray(items)), Box::new(index))
+            }
+            6 => {
+                let u = self.random_type(2);
+                let first = self.expr(&u, depth);
+                SyntaxTree::Seq(vec![first, self.expr(t, depth)])
+            }
+            7 => {
+                let (u, w) = (self.random_type(1), self.random_type(1));
+                let val =
+                    SyntaxTree::LiteralTuple(vec![self.expr(&u, depth), self.expr(&w, depth)]);
+                let (a, b) = (self.fresh(), self.fresh());
+                let body = self.with_var(a.clone(), u, |g| {
+                    g.with_var(b.clone(), w, |g| g.expr(t, depth))
+                });
+                let pattern = Pattern::Tuple(vec![Pattern::Bind(a), Pattern::Bind(b)]);
+                SyntaxTree::LetPattern(pattern, Box::new(val), Box::new(body))
+            }
+            8 => {
+                let val = self.expr(t, depth);
+                let typ = if self.site() {
+                    TypeInfo::Array(Box::new(t.clone()), None)
+                } else {
+                    t.clone()
+                };
+                SyntaxTree::Ascribe(Box::new(val), Box::new(type_expr(&typ)))
+            }
+            9 => {
+                let body = match self.rng.percent(50) {
+                    true => SyntaxTree::Raise(Box::new(SyntaxTree::LiteralStr(self.string()))),
+                    false => self.expr(t, depth),
+                };
+                let handler = Catch::Any(self.expr(t, depth));
+                SyntaxTree::Try(Box::new(body), vec![handler], None)
+            }
+            10 if matches!(self.returns.last(), Some(x) if option_payload(x).is_some()) => {
+                let val = self.expr(&option_type(t.clone()), depth);
+                SyntaxTree::Propagate(Box::new(val))
+            }
+            11 => {
+                let funcs: Vec<_> = self
+                    .scope
+                    .iter()
+                    .filter(|x| matches!(&x.1, TypeInfo::Func(_, r) if same_type(r, t)))
+                    .cloned()
+                    .collect();
+                if funcs.is_empty() {
+                    return self.specific(t, depth);
+                }
+                let (name, func_t) = funcs[self.rng.below(funcs.len())].clone();
+                let TypeInfo::Func(params, _) = func_t else {
+                    unreachable!()
+                };
+                let args = params.iter().map(|x| self.expr(x, depth)).collect();
+                SyntaxTree::Call(Box::new(SyntaxTree::Ident(name)), args)
+            }
+            12 => self.var_block(t, depth),
+            13 => self.struct_block(t, depth),
+            _ => self.specific(t, depth),
+        }
+    }
+
+    // Wraps the assignment in a function, which cannot assign to the variables it captures
+    fn statement(&mut self, stmt: SyntaxTree) -> SyntaxTree {
+        if self.site() {
+            let func = SyntaxTree::Lambda(vec![], Box::new(stmt));
+            return SyntaxTree::Call(Box::new(func), vec![]);
+        }
+        stmt
+    }
+
+    fn divisor_or_expr(&mut self, op: ArithmeticOp, depth: usize) -> SyntaxTree {
+        match op {
+            ArithmeticOp::Div | ArithmeticOp::Rem => {
+                SyntaxTree::LiteralInt64(1 + self.rng.below(9) as i64)
+            }
+            _ => self.expr(&TypeInfo::Int64, depth),
+        }
+    }
+
+    // `(var x u (seq assignments... body))`, declared with `let` when mutated
+    fn var_block(&mut self, t: &TypeInfo, depth: usize) -> SyntaxTree {
+        let u = [TypeInfo::Int64, TypeInfo::Str][self.rng.below(2)].clone();
+        let name = self.fresh();
+        let stmts = self.with_var(name.clone(), u.clone(), |g| {
+            let mut stmts = Vec::new();
+            for _ in 0..1 + g.rng.below(3) {
+                let place = Place::Var(name.clone());
+                let stmt = if u == TypeInfo::Int64 && g.rng.percent(50) {
+                    let op = OPS[g.rng.below(OPS.len())];
+                    let y = g.divisor_or_expr(op, depth);
+                    SyntaxTree::CompoundSet(op, place, Box::new(y))
+                } else {
+                    SyntaxTree::Set(place, Box::new(g.expr(&u, depth)))
+                };
+                stmts.push(g.statement(stmt));
+            }
+            stmts.push(g.expr(t, depth));
+            stmts
+        });
+        let body = Box::new(SyntaxTree::Seq(stmts));
+        if self.site() {
+            let init = match u {
+                TypeInfo::Int64 => SyntaxTree::LiteralInt64(0),
+                _ => SyntaxTree::LiteralStr("".into()),
+            };
+            return SyntaxTree::LetVal(name, Box::new(init), body);
+        }
+        SyntaxTree::LetType(name, Box::new(type_expr(&u)), body)
+    }
+
+    // A `var` of a struct whose fields are updated through places before the body:
+    // `n` an integer, `a` a growable array, `f` a fixed-length array and `m` a map
+    fn struct_block(&mut self, t: &TypeInfo, depth: usize) -> SyntaxTree {
+        let elem = self.zeroable_type(1);
+        let key = [TypeInfo::Int64, TypeInfo::Str][self.rng.below(2)].clone();
+        let len = 1 + self.rng.below(3);
+        let fixed = TypeInfo::Array(Box::new(elem.clone()), Some(len));
+        let fields = vec![
+            ("n".into(), ident("i64")),
+            (
+                "a".into(),
+                type_expr(&TypeInfo::Array(Box::new(elem.clone()), None)),
+            ),
+            ("f".into(), type_expr(&fixed)),
+            (
+                "m".into(),
+                SyntaxTree::LiteralMapType(Box::new(type_expr(&key)), Box::new(type_expr(&elem))),
+            ),
+        ];
+        let (struct_name, v) = (self.fresh(), self.fresh());
+        let mut stmts = Vec::new();
+        for _ in 0..1 + self.rng.below(4) {
+            let stmt = self.struct_statement(&v, &elem, &key, len, depth);
+            stmts.push(self.statement(stmt));
+        }
+        // Reads of what the statements wrote
+        let out = match t {
+            _ if same_type(t, &elem) && self.rng.percent(50) => {
+                let k = self.key(&key);
+                let x = self.expr(&elem, depth);
+                let insert =
+                    SyntaxTree::MapUpdate(MapUpdate::Insert, field(&v, "m"), vec![k.clone(), x]);
+                stmts.push(insert);
+                SyntaxTree::MapOp(MapOp::Get, vec![field_get(&v, "m"), k])
+            }
+            TypeInfo::Int64 if self.rng.percent(50) => match self.rng.below(3) {
+                0 => field_get(&v, "n"),
+                1 => form("array-len", vec![field_get(&v, "a")]),
+                _ => SyntaxTree::MapOp(MapOp::Len, vec![field_get(&v, "m")]),
+            },
+            _ => self.expr(t, depth),
+        };
+        stmts.push(out);
+        let body = SyntaxTree::LetType(
+            v,
+            Box::new(SyntaxTree::Ident(struct_name.clone())),
+            Box::new(SyntaxTree::Seq(stmts)),
+        );
+        SyntaxTree::LetVal(
+            struct_name,
+            Box::new(SyntaxTree::LiteralStructType(fields)),
+            Box::new(body),
+        )
+    }
+
+    fn key(&mut self, t: &TypeInfo) -> SyntaxTree {
+        match t {
+            TypeInfo::Int64 => SyntaxTree::LiteralInt64(self.rng.int()),
+            _ => SyntaxTree::LiteralStr(self.string()),
+        }
+    }
+
+    // Statements that never fail at runtime: indices stay below the fixed length, pops
+    // follow pushes and slices stay within the fixed-length array
+    fn struct_statement(
+        &mut self,
+        v: &Rc<str>,
+        elem: &TypeInfo,
+        key: &TypeInfo,
+        len: usize,
+        depth: usize,
+    ) -> SyntaxTree {
+        match self.rng.below(9) {
+            0 => SyntaxTree::Set(field(v, "n"), Box::new(self.expr(&TypeInfo::Int64, depth))),
+            1 => {
+                let op = OPS[self.rng.below(OPS.len())];
+                let y = self.divisor_or_expr(op, depth);
+                SyntaxTree::CompoundSet(op, field(v, "n"), Box::new(y))
+            }
+            2 => {
+                let x = self.expr(elem, depth);
+                // Fixed-length arrays cannot grow
+                let target = if self.site() { "f" } else { "a" };
+                SyntaxTree::ArrayOp(ArrayOp::Push, field(v, target), vec![x])
+            }
+            3 => {
+                let x = self.expr(elem, depth);
+                SyntaxTree::Seq(vec![
+                    SyntaxTree::ArrayOp(ArrayOp::Push, field(v, "a"), vec![x]),
+                    SyntaxTree::ArrayOp(ArrayOp::Pop, field(v, "a"), vec![]),
+                ])
+            }
+            4 => {
+                let (k, x) = (self.key(key), self.expr(elem, depth));
+                SyntaxTree::MapUpdate(MapUpdate::Insert, field(v, "m"), vec![k, x])
+            }
+            5 => {
+                let k = self.key(key);
+                if self.site() {
+                    // Only variables, fields and elements are places
+                    let tuple = SyntaxTree::LiteralTuple(vec![field_get(v, "m")]);
+                    let target = SyntaxTree::TupleGet(Box::new(tuple), 0);
+                    return SyntaxTree::Call(Box::new(ident("map-remove")), vec![target, k]);
+                }
+                SyntaxTree::MapUpdate(MapUpdate::Remove, field(v, "m"), vec![k])
+            }
+            6 => {
+                let index = SyntaxTree::LiteralInt64(self.rng.below(len) as i64);
+                let place = Place::Index(Box::new(field(v, "f")), Box::new(index));
+                SyntaxTree::Set(place, Box::new(self.expr(elem, depth)))
+            }
+            7 => {
+                let end = self.rng.below(len + 1);
+                let start = self.rng.below(end + 1);
+                let slice = form(
+                    "array-slice",
+                    vec![
+                        field_get(v, "f"),
+                        SyntaxTree::LiteralInt64(start as i64),
+                        SyntaxTree::LiteralInt64(end as i64),
+                    ],
+                );
+                // Slices have an unknown length, even of the whole array
+                let target = if self.site() { "f" } else { "a" };
+                SyntaxTree::Set(field(v, target), Box::new(slice))
+            }
+            _ => {
+                let items = (0..len).map(|_| self.expr(elem, depth)).collect();
+                SyntaxTree::Set(field(v, "f"), Box::new(SyntaxTree::LiteralArray(items)))
+            }
+        }
+    }
+
+    // Forms that only build values of the type
+    fn specific(&mut self, t: &TypeInfo, depth: usize) -> SyntaxTree {
+        match t {
+            TypeInfo::Int64 => {
+                let op = OPS[self.rng.below(OPS.len())];
+                let x = self.expr(t, depth);
+                // Divisors are never zero, every other error is a type error
+                let y = self.divisor_or_expr(op, depth);
+                let y = if self.site() {
+                    SyntaxTree::LiteralStr(self.string())
+                } else {
+                    y
+                };
+                SyntaxTree::Arithmetic(op, vec![x, y])
+            }
+            TypeInfo::Array(inner, None) if self.rng.percent(50) => {
+                let u = self.random_type(1);
+                let func = self.lambda(std::slice::from_ref(&u), inner, depth);
+                let array = self.expr(&TypeInfo::Array(Box::new(u), None), depth);
+                form("array-map", vec![func, array])
+            }
+            TypeInfo::Array(inner, Some(len)) => {
+                SyntaxTree::LiteralArray((0..*len).map(|_| self.expr(inner, depth)).collect())
+            }
+            TypeInfo::Array(inner, None) => {
+                let len = self.rng.below(4);
+                let items = (0..len).map(|_| self.expr(inner, depth)).collect();
+                self.unsized_array(t, items)
+            }
+            TypeInfo::Tuple(items) => {
+                SyntaxTree::LiteralTuple(items.iter().map(|x| self.expr(x, depth)).collect())
+            }
+            TypeInfo::Func(params, ret) => self.lambda(params, ret, depth),
+            TypeInfo::Enum(_) => {
+                let payload = option_payload(t).unwrap().clone();
+                let x = self.expr(&payload, depth);
+                self.variant_some(t, x)
+            }
+            _ => self.leaf(t),
+        }
+    }
+}
+
+// The program, its type and the number of mutation sites it has
+fn well_typed(seed: u64) -> (SyntaxTree, TypeInfo, usize) {
+    let mut g = Generator::new(seed, None);
+    let t = g.random_type(2);
+    let tree = g.expr(&t, 4);
+    (tree, t, g.sites)
+}
+
+fn mutant(seed: u64, site: usize) -> SyntaxTree {
+    let mut g = Generator::new(seed, Some(site));
+    let t = g.random_type(2);
+    g.expr(&t, 4)
+}
+
+// Runs the cases on a thread with a deep stack, reporting the first one that panics or fails
+fn check<T: Debug + Send + 'static>(
+    cases: impl Iterator<Item = T> + Send + 'static,
+    f: fn(&T) -> Result<(), String>,
+) {
+    let thread = std::thread::Builder::new()
+        .stack_size(1 << 28)
+        .spawn(move || {
+            for case in cases {
+                match catch_unwind(AssertUnwindSafe(|| f(&case))) {
+                    Ok(Ok(())) => {}
+                    Ok(Err(err)) => return Err(format!("Case {case:?}: {err}")),
+                    Err(_) => return Err(format!("Case {case:?} panicked")),
+                }
+            }
+            Ok(())
+        });
+    if let Err(err) = thread.unwrap().join().unwrap() {
+        panic!("{err}");
+    }
+}
+
+// Every generated program is accepted and evaluates to a value of the checked type
+fn soundness(seed: &u64) -> Result<(), String> {
+    let (tree, t, _) = well_typed(*seed);
+    check_sound(&tree, &t).map_err(|err| format!("{err}\nin `{tree}`"))
+}
+
+fn check_sound(tree: &SyntaxTree, t: &TypeInfo) -> Result<(), String> {
+    let tokens: TokenTree = tree.to_string().parse()?;
+    let parsed = SyntaxTree::try_from(&tokens)?;
+    if parsed != *tree {
+        return Err(format!("Printed as `{tree}`, parsed back as `{parsed}`"));
+    }
+    let typed = TypedTree::try_from(&parsed)?;
+    let val = interpret_no_context(&tokens)?;
+    if !has_type(&val, typed.type_info()) || !has_type(&val, t) {
+        return Err(format!("{val} is not of type {}", typed.type_info()));
+    }
+    Ok(())
+}
+
+// Each single mutation is rejected before evaluation
+fn mutants_rejected(seed: &u64) -> Result<(), String> {
+    let (_, _, sites) = well_typed(*seed);
+    let mut rng = Rng(*seed);
+    for _ in 0..MUTATIONS_PER_PROGRAM.min(sites) {
+        let tree = mutant(*seed, rng.below(sites));
+        let tokens: TokenTree = tree.to_string().parse()?;
+        let accepted = SyntaxTree::try_from(&tokens).and_then(|x| TypedTree::try_from(&x));
+        if accepted.is_ok() {
+            return Err(format!("Accepted the mutant `{tree}`"));
+        }
+    }
+    Ok(())
+}
+
+fn soup(seed: u64) -> String {
+    const TOKENS: &[&str] = &[
+        "(",
+        "(",
+        "(",
+        ")",
+        ")",
+        ")",
+        " ",
+        " ",
+        "\n",
+        "'",
+        "`",
+        ",",
+        ",@",
+        "\"",
+        "\\",
+        "x",
+        "1",
+        "-7",
+        "let",
+        "fn",
+        "match",
+        "array",
+        "tuple",
+        "+",
+        "/",
+        "seq",
+        "i64",
+        "str",
+        "var",
+        "set",
+        "?",
+        "try",
+        "catch",
+        "raise",
+        "import",
+        "module",
+        "deftest",
+        "assert-eq",
+        "quote",
+        "eval",
+        "defmacro",
+        "syntax-rules",
+        "...",
+        "_",
+        "(Option",
+        "Some",
+        "None",
+        "variant",
+        "array-get",
+    ];
+    let mut rng = Rng(seed);
+    let mut out = String::from("(");
+    for _ in 0..rng.below(40) {
+        out.push_str(TOKENS[rng.below(TOKENS.len())]);
+        if rng.percent(70) {
+            out.push(' ');
+        }
+    }
+    out
+}
+
+// Balanced lists of pattern variables, literals and ellipses
+fn pattern_soup(rng: &mut Rng) -> String {
+    const TOKENS: &[&str] = &["x", "y", "1", "_", "...", "...", "(", "(", ")"];
+    let mut out = String::new();
+    let mut depth = 0;
+    for _ in 0..rng.below(8) {
+        let token = TOKENS[rng.below(TOKENS.len())];
+        match token {
+            ")" if depth == 0 => continue,
+            ")" => depth -= 1,
+            "(" => depth += 1,
+            _ => {}
+        }
+        out.push_str(token);
+        out.push(' ');
+    }
+    out + &")".repeat(depth)
+}
+
+// A macro with a random rule, used on random arguments
+fn macro_soup(seed: u64) -> String {
+    let mut rng = Rng(seed);
+    let (pattern, args) = (pattern_soup(&mut rng), pattern_soup(&mut rng));
+    format!("(defmacro m (syntax-rules () ((_ {pattern}) 0)) (m {args}))")
+}
+
+// No stage panics on arbitrary input, and whatever the checker accepts evaluates soundly
+fn no_panics(seed: &u64) -> Result<(), String> {
+    let source = match seed % 2 {
+        0 => soup(*seed),
+        _ => macro_soup(*seed),
+    };
+    _ = item_spans(&source);
+    let Ok(tokens) = source.parse::<TokenTree>() else {
+        return Ok(());
+    };
+    let Ok(typed) = SyntaxTree::try_from(&tokens).and_then(|x| TypedTree::try_from(&x)) else {
+        return Ok(());
+    };
+    match interpret_no_context(&tokens) {
+        Ok(val) if !has_type(&val, typed.type_info()) => Err(format!(
+            "{val} is not of type {} in `{source}`",
+            typed.type_info()
+        )),
+        _ => Ok(()),
+    }
+}
+
+#[test]
+fn generated_programs_are_sound() {
+    check(0..PROGRAMS, soundness);
+}
+
+#[test]
+fn mutated_programs_are_rejected() {
+    check(0..PROGRAMS, mutants_rejected);
+}
+
+#[test]
+fn parsing_never_panics() {
+    check(0..SOUPS, no_panics);
+}
@@ -1,5 +1,7 @@
-use crate::{guard, match_ok, token_tree::TokenTree};
-use std::{fmt::Write, rc::Rc};
+use crate::forms::{self, Form};
+use crate::{guard, macros, match_ok, modules, token_tree::TokenTree};
+use std::fmt::{self, Display, Write};
+use std::rc::Rc;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum ArithmeticOp {
@@ -10,36 +12,392 @@ pub enum ArithmeticOp {
     Rem,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MapOp {
+    Get,
+    Contains,
+    Len,
+    Keys,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum MapUpdate {
+    Insert,
+    Remove,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ArrayOp {
+    Push,
+    Pop,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum SyntaxTree {
     Ident(Rc<str>),
     LetVal(Rc<str>, Box<SyntaxTree>, Box<SyntaxTree>),
     LetType(Rc<str>, Box<SyntaxTree>, Box<SyntaxTree>),
     Seq(Vec<SyntaxTree>),
-    Set(Rc<str>, Box<SyntaxTree>),
+    Set(Place, Box<SyntaxTree>),
+    CompoundSet(ArithmeticOp, Place, Box<SyntaxTree>),
     LiteralInt64(i64),
     LiteralArray(Vec<SyntaxTree>),
-    LiteralArrayType(Box<SyntaxTree>),
+    LiteralArrayType(Box<SyntaxTree>, Option<usize>),
     Arithmetic(ArithmeticOp, Vec<SyntaxTree>),
     ArrayGet(Box<SyntaxTree>, Box<SyntaxTree>),
-    ArraySet(Box<SyntaxTree>, Box<SyntaxTree>, Box<SyntaxTree>),
+    ArrayOp(ArrayOp, Place, Vec<SyntaxTree>),
+    Form(Form, Vec<SyntaxTree>),
+    LiteralStructType(Vec<(Rc<str>, SyntaxTree)>),
+    LiteralStruct(Box<SyntaxTree>, Vec<(Rc<str>, SyntaxTree)>),
+    FieldGet(Box<SyntaxTree>, Rc<str>),
+    LiteralEnumType(Vec<(Rc<str>, Vec<SyntaxTree>)>),
+    LiteralVariant(Box<SyntaxTree>, Rc<str>, Vec<SyntaxTree>),
+    Match(Box<SyntaxTree>, Vec<(Pattern, SyntaxTree)>),
+    LetPattern(Pattern, Box<SyntaxTree>, Box<SyntaxTree>),
+    LiteralTupleType(Vec<SyntaxTree>),
+    LiteralTuple(Vec<SyntaxTree>),
+    TupleGet(Box<SyntaxTree>, usize),
+    LiteralStr(Rc<str>),
+    LiteralMapType(Box<SyntaxTree>, Box<SyntaxTree>),
+    LiteralMap(Vec<(SyntaxTree, SyntaxTree)>),
+    MapOp(MapOp, Vec<SyntaxTree>),
+    MapUpdate(MapUpdate, Place, Vec<SyntaxTree>),
+    Ascribe(Box<SyntaxTree>, Box<SyntaxTree>),
+    Lambda(Vec<(Rc<str>, Option<SyntaxTree>)>, Box<SyntaxTree>),
+    LetRec(Rc<str>, Box<SyntaxTree>, Box<SyntaxTree>),
+    Call(Box<SyntaxTree>, Vec<SyntaxTree>),
+    LiteralFuncType(Vec<SyntaxTree>, Box<SyntaxTree>),
+    Generic(Vec<Rc<str>>, Box<SyntaxTree>),
+    Quote(TokenTree),
+    Quasiquote(Quasi),
+    Raise(Box<SyntaxTree>),
+    Try(Box<SyntaxTree>, Vec<Catch>, Option<Box<SyntaxTree>>),
+    Propagate(Box<SyntaxTree>),
+}
+
+// Handler of a `try`, tried in order against the raised value
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Catch {
+    // `(catch _ handler)` handles anything
+    Any(SyntaxTree),
+    // `(catch (var type) handler)` handles values of the type
+    Typed(Rc<str>, SyntaxTree, SyntaxTree),
+}
+
+// Template of a `quasiquote`: data copied as is, with holes filled in by evaluated expressions
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Quasi {
+    Datum(TokenTree),
+    List(Vec<Quasi>),
+    Unquote(Box<SyntaxTree>),
+    // Only allowed as a list item, its elements become items of the enclosing list
+    Splice(Box<SyntaxTree>),
+}
+
+// Assignable location: a variable or an element or field nested inside one
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Place {
+    Var(Rc<str>),
+    Index(Box<Place>, Box<SyntaxTree>),
+    Field(Box<Place>, Rc<str>),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Pattern {
+    Wildcard,
+    Bind(Rc<str>),
+    LiteralInt64(i64),
+    Variant(Rc<str>, Vec<Pattern>),
+    Tuple(Vec<Pattern>),
+}
+
+impl Place {
+    pub fn root(&self) -> &str {
+        match self {
+            Self::Var(x) => x,
+            Self::Index(x, _) | Self::Field(x, _) => x.root(),
+        }
+    }
+}
+
+fn into_place(error_log: &mut String, tree: SyntaxTree) -> Option<Place> {
+    match tree {
+        SyntaxTree::Ident(x) => Some(Place::Var(x)),
+        SyntaxTree::ArrayGet(array, index) => Some(Place::Index(
+            Box::new(into_place(error_log, *array)?),
+            index,
+        )),
+        SyntaxTree::FieldGet(record, field) => Some(Place::Field(
+            Box::new(into_place(error_log, *record)?),
+            field,
+        )),
+        _ => {
+            writeln!(error_log, "Cannot assign to `{tree}`").unwrap();
+            None
+        }
+    }
+}
+
+fn into_field_list(
+    error_log: &mut String,
+    items: &[TokenTree],
+) -> Option<Vec<(Rc<str>, SyntaxTree)>> {
+    let mut out_opt = Some(Vec::new());
+    for item in items {
+        let pair_opt = match_ok!(
+            error_log,
+            item,
+            TokenTree::Array(x) if x.len() == 2 => x,
+            format!("Expected `(field value)`, found `{item}`")
+        );
+        let field_opt = pair_opt.and_then(|pair| {
+            let name_opt = match_ok!(
+                error_log,
+                &pair[0],
+                TokenTree::Atom(x) => x.clone(),
+                format!("Expected a field name, found `{}`", pair[0])
+            );
+            let val_opt = into_syntax_tree(error_log, &pair[1]);
+            Some((name_opt?, val_opt?))
+        });
+        match (&mut out_opt, field_opt) {
+            (Some(out), Some((name, val))) => {
+                if out.iter().any(|(x, _)| *x == name) {
+                    writeln!(error_log, "Duplicate field {name:?}").unwrap();
+                    out_opt = None;
+                } else {
+                    out.push((name, val));
+                }
+            }
+            _ => out_opt = None,
+        }
+    }
+    out_opt
+}
+
+pub fn into_syntax_list(error_log: &mut String, items: &[TokenTree]) -> Option<Vec<SyntaxTree>> {
+    let mut out_opt = Some(Vec::new());
+    for item in items {
+        let item_opt = into_syntax_tree(error_log, item);
+        match (&mut out_opt, item_opt) {
+            (Some(out), Some(item)) => out.push(item),
+            _ => out_opt = None,
+        }
+    }
+    out_opt
+}
+
+fn into_pattern(error_log: &mut String, tree: &TokenTree) -> Option<Pattern> {
+    match tree {
+        TokenTree::Atom(x) if &x[..] == "_" => Some(Pattern::Wildcard),
+        TokenTree::Atom(x) => Some(Pattern::Bind(x.clone())),
+        TokenTree::Int64(x) => Some(Pattern::LiteralInt64(*x)),
+        TokenTree::Str(x) => {
+            writeln!(error_log, "String {x:?} used as pattern").unwrap();
+            None
+        }
+        TokenTree::Array(subtree) => {
+            guard!(error_log, !subtree.is_empty(), "Empty pattern `()`");
+            let name_opt = match_ok!(
+                error_log,
+                &subtree[0],
+                TokenTree::Atom(x) => x.clone(),
+                format!("Expected a variant name, found `{}` in `{tree}`", subtree[0])
+            );
+            let mut out_opt = Some(Vec::new());
+            for it in &subtree[1..] {
+                let item_opt = into_pattern(error_log, it);
+                match (&mut out_opt, item_opt) {
+                    (Some(out), Some(item)) => out.push(item),
+                    _ => out_opt = None,
+                }
+            }
+            match name_opt? {
+                name if &name[..] == "tuple" => Some(Pattern::Tuple(out_opt?)),
+                name => Some(Pattern::Variant(name, out_opt?)),
+            }
+        }
+    }
+}
+
+// Patterns on the left of `let` are irrefutable, so only names and nested tuples are allowed
+fn into_let_pattern(error_log: &mut String, tree: &TokenTree) -> Option<Pattern> {
+    match tree {
+        TokenTree::Atom(x) if &x[..] == "_" => Some(Pattern::Wildcard),
+        TokenTree::Atom(x) => Some(Pattern::Bind(x.clone())),
+        TokenTree::Array(subtree) => {
+            let mut out_opt = Some(Vec::new());
+            for it in subtree {
+                let item_opt = into_let_pattern(error_log, it);
+                match (&mut out_opt, item_opt) {
+                    (Some(out), Some(item)) => out.push(item),
+                    _ => out_opt = None,
+                }
+            }
+            Some(Pattern::Tuple(out_opt?))
+        }
+        TokenTree::Int64(x) => {
+            writeln!(error_log, "Number {x} used as let binding").unwrap();
+            None
+        }
+        TokenTree::Str(x) => {
+            writeln!(error_log, "String {x:?} used as let binding").unwrap();
+            None
+        }
+    }
+}
+
+fn quasi_head(tree: &TokenTree) -> Option<(&str, &[TokenTree])> {
+    match tree {
+        TokenTree::Array(items) => match items.first() {
+            Some(TokenTree::Atom(head))
+                if matches!(&head[..], "quasiquote" | "unquote" | "unquote-splicing") =>
+            {
+                Some((head, &items[1..]))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// Nested `quasiquote`s go one level deeper and unquotes step one level out;
+// only the unquotes at level zero get evaluated
+fn into_quasi(error_log: &mut String, tree: &TokenTree, depth: usize) -> Option<Quasi> {
+    match quasi_head(tree) {
+        Some(("unquote", args)) if depth == 0 => {
+            guard!(
+                error_log,
+                args.len() == 1,
+                format!("Expected one operand in `{tree}`")
+            );
+            let x = into_syntax_tree(error_log, &args[0])?;
+            Some(Quasi::Unquote(Box::new(x)))
+        }
+        Some(("unquote-splicing", _)) if depth == 0 => {
+            writeln!(
+                error_log,
+                "`unquote-splicing` outside of a list in `{tree}`"
+            )
+            .unwrap();
+            None
+        }
+        Some((head, args)) => {
+            guard!(
+                error_log,
+                args.len() == 1,
+                format!("Expected one operand in `{tree}`")
+            );
+            let depth = if head == "quasiquote" {
+                depth + 1
+            } else {
+                depth - 1
+            };
+            let x = into_quasi(error_log, &args[0], depth)?;
+            Some(Quasi::List(vec![
+                Quasi::Datum(TokenTree::Atom(head.into())),
+                x,
+            ]))
+        }
+        None => {
+            let TokenTree::Array(items) = tree else {
+                return Some(Quasi::Datum(tree.clone()));
+            };
+            let mut out_opt = Some(Vec::new());
+            for it in items {
+                let it_opt = match quasi_head(it) {
+                    Some(("unquote-splicing", args)) if depth == 0 => {
+                        guard!(
+                            error_log,
+                            args.len() == 1,
+                            format!("Expected one operand in `{it}`")
+                        );
+                        into_syntax_tree(error_log, &args[0]).map(|x| Quasi::Splice(Box::new(x)))
+                    }
+                    _ => into_quasi(error_log, it, depth),
+                };
+                match (&mut out_opt, it_opt) {
+                    (Some(out), Some(it)) => out.push(it),
+                    _ => out_opt = None,
+                }
+            }
+            Some(Quasi::List(out_opt?))
+        }
+    }
+}
+
+fn into_catch(error_log: &mut String, tree: &TokenTree) -> Option<Catch> {
+    if let TokenTree::Array(items) = tree
+        && let [TokenTree::Atom(head), binding, handler] = &items[..]
+        && &head[..] == "catch"
+    {
+        match binding {
+            TokenTree::Atom(x) if &x[..] == "_" => {
+                return Some(Catch::Any(into_syntax_tree(error_log, handler)?));
+            }
+            TokenTree::Array(binding) => {
+                if let [TokenTree::Atom(var), typ] = &binding[..] {
+                    let typ_opt = into_syntax_tree(error_log, typ);
+                    let handler_opt = into_syntax_tree(error_log, handler);
+                    return Some(Catch::Typed(var.clone(), typ_opt?, handler_opt?));
+                }
+            }
+            _ => {}
+        }
+    }
+    writeln!(
+        error_log,
+        "Expected `(catch (var type) handler)` or `(catch _ handler)`, found `{tree}`"
+    )
+    .unwrap();
+    None
 }
 
 fn into_syntax_tree(error_log: &mut String, tree1: &TokenTree) -> Option<SyntaxTree> {
     match tree1 {
         TokenTree::Atom(x) => Some(SyntaxTree::Ident(x.clone())),
         TokenTree::Array(subtree) => {
-            guard!(error_log, !subtree.is_empty());
-            let head = match_ok!(error_log, &subtree[0], TokenTree::Atom(x) => x)?;
+            guard!(
+                error_log,
+                !subtree.is_empty(),
+                "Empty list `()` is not an expression"
+            );
+            let malformed = |usage: &str| format!("Malformed `{tree1}`, expected `{usage}`");
+            let TokenTree::Atom(head) = &subtree[0] else {
+                let func_opt = into_syntax_tree(error_log, &subtree[0]);
+                let args_opt = into_syntax_list(error_log, &subtree[1..]);
+                return Some(SyntaxTree::Call(Box::new(func_opt?), args_opt?));
+            };
             match &head[..] {
+                "let" if matches!(subtree.get(1), Some(TokenTree::Array(_))) => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 4,
+                        malformed("(let (pattern...) value body)")
+                    );
+                    let pattern_opt = into_let_pattern(error_log, &subtree[1]);
+                    let val_opt = into_syntax_tree(error_log, &subtree[2]);
+                    let body_opt = into_syntax_tree(error_log, &subtree[3]);
+                    Some(SyntaxTree::LetPattern(
+                        pattern_opt?,
+                        Box::new(val_opt?),
+                        Box::new(body_opt?),
+                    ))
+                }
                 "let" => {
-                    guard!(error_log, subtree.len() == 4);
-                    let var_res = into_syntax_tree(error_log, &subtree[1]);
-                    let val_res = into_syntax_tree(error_log, &subtree[2]);
-                    let body_res = into_syntax_tree(error_log, &subtree[3]);
-                    let var_opt = match_ok!(error_log, var_res, Some(SyntaxTree::Ident(x)) => x);
-                    let val_opt = match_ok!(error_log, val_res, Some(x) => x);
-                    let body_opt = match_ok!(error_log, body_res, Some(x) => x);
+                    guard!(
+                        error_log,
+                        subtree.len() == 4,
+                        malformed("(let name value body)")
+                    );
+                    let var_opt = match_ok!(
+                        error_log,
+                        &subtree[1],
+                        TokenTree::Atom(x) => x.clone(),
+                        malformed("(let name value body)")
+                    );
+                    let val_opt = into_syntax_tree(error_log, &subtree[2]);
+                    let body_opt = into_syntax_tree(error_log, &subtree[3]);
                     Some(SyntaxTree::LetVal(
                         var_opt?,
                         Box::new(val_opt?),
@@ -47,13 +405,19 @@ fn into_syntax_tree(error_log: &mut String, tree1: &TokenTree) -> Option<SyntaxT
                     ))
                 }
                 "var" => {
-                    guard!(error_log, subtree.len() == 4);
-                    let var_res = into_syntax_tree(error_log, &subtree[1]);
-                    let type_res = into_syntax_tree(error_log, &subtree[2]);
-                    let body_res = into_syntax_tree(error_log, &subtree[3]);
-                    let var_opt = match_ok!(error_log, var_res, Some(SyntaxTree::Ident(x)) => x);
-                    let type_opt = match_ok!(error_log, type_res, Some(x) => x);
-                    let body_opt = match_ok!(error_log, body_res, Some(x) => x);
+                    guard!(
+                        error_log,
+                        subtree.len() == 4,
+                        malformed("(var name type body)")
+                    );
+                    let var_opt = match_ok!(
+                        error_log,
+                        &subtree[1],
+                        TokenTree::Atom(x) => x.clone(),
+                        malformed("(var name type body)")
+                    );
+                    let type_opt = into_syntax_tree(error_log, &subtree[2]);
+                    let body_opt = into_syntax_tree(error_log, &subtree[3]);
                     Some(SyntaxTree::LetType(
                         var_opt?,
                         Box::new(type_opt?),
@@ -61,7 +425,7 @@ fn into_syntax_tree(error_log: &mut String, tree1: &TokenTree) -> Option<SyntaxT
                     ))
                 }
                 "seq" => {
-                    guard!(error_log, subtree.len() > 1);
+                    guard!(error_log, subtree.len() > 1, malformed("(seq expr...)"));
                     let mut out_opt = Some(Vec::new());
                     for subtree_it in &subtree[1..] {
                         let item_opt = into_syntax_tree(error_log, subtree_it);
@@ -73,11 +437,34 @@ fn into_syntax_tree(error_log: &mut String, tree1: &TokenTree) -> Option<SyntaxT
                     Some(SyntaxTree::Seq(out_opt?))
                 }
                 "set" => {
-                    guard!(error_log, subtree.len() == 3);
-                    let var_res = into_syntax_tree(error_log, &subtree[1]);
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(set place value)")
+                    );
+                    let place_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let place_opt = place_opt.and_then(|x| into_place(error_log, x));
+                    let val_opt = into_syntax_tree(error_log, &subtree[2]);
+                    Some(SyntaxTree::Set(place_opt?, Box::new(val_opt?)))
+                }
+                "+=" | "-=" | "*=" | "/=" | "%=" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed(&format!("({head} place value)"))
+                    );
+                    let op = match &head[..] {
+                        "+=" => ArithmeticOp::Add,
+                        "-=" => ArithmeticOp::Sub,
+                        "*=" => ArithmeticOp::Mul,
+                        "/=" => ArithmeticOp::Div,
+                        "%=" => ArithmeticOp::Rem,
+                        _ => return None,
+                    };
+                    let place_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let place_opt = place_opt.and_then(|x| into_place(error_log, x));
                     let val_opt = into_syntax_tree(error_log, &subtree[2]);
-                    let var_opt = match_ok!(error_log, var_res, Some(SyntaxTree::Ident(x)) => x);
-                    Some(SyntaxTree::Set(var_opt?, Box::new(val_opt?)))
+                    Some(SyntaxTree::CompoundSet(op, place_opt?, Box::new(val_opt?)))
                 }
                 "array" => {
                     let mut out_opt = Some(Vec::new());
@@ -91,9 +478,24 @@ fn into_syntax_tree(error_log: &mut String, tree1: &TokenTree) -> Option<SyntaxT
                     Some(SyntaxTree::LiteralArray(out_opt?))
                 }
                 "array-t" => {
-                    guard!(error_log, subtree.len() == 2);
+                    guard!(
+                        error_log,
+                        subtree.len() == 2 || subtree.len() == 3,
+                        malformed("(array-t type [length])")
+                    );
                     let inner_opt = into_syntax_tree(error_log, &subtree[1]);
-                    Some(SyntaxTree::LiteralArrayType(Box::new(inner_opt?)))
+                    let len_opt = match subtree.get(2) {
+                        None => Some(None),
+                        Some(len) => {
+                            match_ok!(
+                                error_log,
+                                len,
+                                &TokenTree::Int64(x) if x >= 0 => Some(x as usize),
+                                malformed("(array-t type [length])")
+                            )
+                        }
+                    };
+                    Some(SyntaxTree::LiteralArrayType(Box::new(inner_opt?), len_opt?))
                 }
                 "+" | "-" | "*" | "/" | "%" => {
                     let op = match &head[..] {
@@ -115,37 +517,750 @@ fn into_syntax_tree(error_log: &mut String, tree1: &TokenTree) -> Option<SyntaxT
                     Some(SyntaxTree::Arithmetic(op, out_opt?))
                 }
                 "array-get" => {
-                    guard!(error_log, subtree.len() == 3);
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(array-get array index)")
+                    );
                     let array_opt = into_syntax_tree(error_log, &subtree[1]);
                     let index_opt = into_syntax_tree(error_log, &subtree[2]);
                     Some(SyntaxTree::ArrayGet(Box::new(array_opt?), Box::new(index_opt?)))
                 }
                 "array-set" => {
-                    guard!(error_log, subtree.len() == 4);
+                    guard!(
+                        error_log,
+                        subtree.len() == 4,
+                        malformed("(array-set array index value)")
+                    );
                     let array_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let array_opt = array_opt.and_then(|x| into_place(error_log, x));
                     let index_opt = into_syntax_tree(error_log, &subtree[2]);
                     let val_opt = into_syntax_tree(error_log, &subtree[3]);
-                    Some(SyntaxTree::ArraySet(
-                        Box::new(array_opt?),
-                        Box::new(index_opt?),
+                    let place = Place::Index(Box::new(array_opt?), Box::new(index_opt?));
+                    Some(SyntaxTree::Set(place, Box::new(val_opt?)))
+                }
+                "struct" => {
+                    let fields_opt = into_field_list(error_log, &subtree[1..]);
+                    Some(SyntaxTree::LiteralStructType(fields_opt?))
+                }
+                "record" => {
+                    guard!(
+                        error_log,
+                        subtree.len() > 1,
+                        malformed("(record type (field value)...)")
+                    );
+                    let type_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let fields_opt = into_field_list(error_log, &subtree[2..]);
+                    Some(SyntaxTree::LiteralStruct(Box::new(type_opt?), fields_opt?))
+                }
+                "field-get" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(field-get record field)")
+                    );
+                    let record_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let field_opt = match_ok!(
+                        error_log,
+                        &subtree[2],
+                        TokenTree::Atom(x) => x.clone(),
+                        malformed("(field-get record field)")
+                    );
+                    Some(SyntaxTree::FieldGet(Box::new(record_opt?), field_opt?))
+                }
+                "field-set" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 4,
+                        malformed("(field-set record field value)")
+                    );
+                    let record_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let record_opt = record_opt.and_then(|x| into_place(error_log, x));
+                    let field_opt = match_ok!(
+                        error_log,
+                        &subtree[2],
+                        TokenTree::Atom(x) => x.clone(),
+                        malformed("(field-set record field value)")
+                    );
+                    let val_opt = into_syntax_tree(error_log, &subtree[3]);
+                    let place = Place::Field(Box::new(record_opt?), field_opt?);
+                    Some(SyntaxTree::Set(place, Box::new(val_opt?)))
+                }
+                "enum" => {
+                    guard!(
+                        error_log,
+                        subtree.len() > 1,
+                        malformed("(enum (name type...)...)")
+                    );
+                    let mut out_opt = Some(Vec::new());
+                    for it in &subtree[1..] {
+                        let variant_opt = match_ok!(
+                            error_log,
+                            it,
+                            TokenTree::Array(x) if !x.is_empty() => x,
+                            malformed("(enum (name type...)...)")
+                        );
+                        let variant_opt = variant_opt.and_then(|variant| {
+                            let name_opt = match_ok!(
+                                error_log,
+                                &variant[0],
+                                TokenTree::Atom(x) => x.clone(),
+                                malformed("(enum (name type...)...)")
+                            );
+                            let payload_opt = into_syntax_list(error_log, &variant[1..]);
+                            Some((name_opt?, payload_opt?))
+                        });
+                        match (&mut out_opt, variant_opt) {
+                            (Some(out), Some((name, payload))) => {
+                                if out.iter().any(|(x, _)| *x == name) {
+                                    writeln!(error_log, "Duplicate variant {name:?}").unwrap();
+                                    out_opt = None;
+                                } else {
+                                    out.push((name, payload));
+                                }
+                            }
+                            _ => out_opt = None,
+                        }
+                    }
+                    Some(SyntaxTree::LiteralEnumType(out_opt?))
+                }
+                "variant" => {
+                    guard!(
+                        error_log,
+                        subtree.len() > 2,
+                        malformed("(variant type name value...)")
+                    );
+                    let type_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let name_opt = match_ok!(
+                        error_log,
+                        &subtree[2],
+                        TokenTree::Atom(x) => x.clone(),
+                        malformed("(variant type name value...)")
+                    );
+                    let payload_opt = into_syntax_list(error_log, &subtree[3..]);
+                    Some(SyntaxTree::LiteralVariant(
+                        Box::new(type_opt?),
+                        name_opt?,
+                        payload_opt?,
+                    ))
+                }
+                "match" => {
+                    guard!(
+                        error_log,
+                        subtree.len() > 2,
+                        malformed("(match value (pattern body)...)")
+                    );
+                    let val_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let mut out_opt = Some(Vec::new());
+                    for it in &subtree[2..] {
+                        let arm_opt = match_ok!(
+                            error_log,
+                            it,
+                            TokenTree::Array(x) if x.len() == 2 => x,
+                            malformed("(match value (pattern body)...)")
+                        );
+                        let arm_opt = arm_opt.and_then(|arm| {
+                            let pattern_opt = into_pattern(error_log, &arm[0]);
+                            let body_opt = into_syntax_tree(error_log, &arm[1]);
+                            Some((pattern_opt?, body_opt?))
+                        });
+                        match (&mut out_opt, arm_opt) {
+                            (Some(out), Some(arm)) => out.push(arm),
+                            _ => out_opt = None,
+                        }
+                    }
+                    Some(SyntaxTree::Match(Box::new(val_opt?), out_opt?))
+                }
+                "tuple-t" => {
+                    let items_opt = into_syntax_list(error_log, &subtree[1..]);
+                    Some(SyntaxTree::LiteralTupleType(items_opt?))
+                }
+                "tuple" => {
+                    let items_opt = into_syntax_list(error_log, &subtree[1..]);
+                    Some(SyntaxTree::LiteralTuple(items_opt?))
+                }
+                "tuple-get" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(tuple-get tuple index)")
+                    );
+                    let tuple_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let index_opt = match_ok!(
+                        error_log,
+                        &subtree[2],
+                        &TokenTree::Int64(x) if x >= 0 => x as usize,
+                        malformed("(tuple-get tuple index)")
+                    );
+                    Some(SyntaxTree::TupleGet(Box::new(tuple_opt?), index_opt?))
+                }
+                "map-t" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(map-t key value)")
+                    );
+                    let key_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let val_opt = into_syntax_tree(error_log, &subtree[2]);
+                    Some(SyntaxTree::LiteralMapType(
+                        Box::new(key_opt?),
                         Box::new(val_opt?),
                     ))
                 }
-                _ => {
-                    writeln!(error_log, "Unknown head {head:?}").unwrap();
+                "map" => {
+                    let mut out_opt = Some(Vec::new());
+                    for it in &subtree[1..] {
+                        let pair_opt = match_ok!(
+                            error_log,
+                            it,
+                            TokenTree::Array(x) if x.len() == 2 => x,
+                            malformed("(map (key value)...)")
+                        );
+                        let pair_opt = pair_opt.and_then(|pair| {
+                            let key_opt = into_syntax_tree(error_log, &pair[0]);
+                            let val_opt = into_syntax_tree(error_log, &pair[1]);
+                            Some((key_opt?, val_opt?))
+                        });
+                        match (&mut out_opt, pair_opt) {
+                            (Some(out), Some(pair)) => out.push(pair),
+                            _ => out_opt = None,
+                        }
+                    }
+                    Some(SyntaxTree::LiteralMap(out_opt?))
+                }
+                "array-push" | "array-pop" => {
+                    let (op, arity, usage) = match &head[..] {
+                        "array-push" => (ArrayOp::Push, 2, "(array-push array value)"),
+                        "array-pop" => (ArrayOp::Pop, 1, "(array-pop array)"),
+                        _ => return None,
+                    };
+                    guard!(error_log, subtree.len() == arity + 1, malformed(usage));
+                    let array_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let array_opt = array_opt.and_then(|x| into_place(error_log, x));
+                    let args_opt = into_syntax_list(error_log, &subtree[2..]);
+                    Some(SyntaxTree::ArrayOp(op, array_opt?, args_opt?))
+                }
+                "map-get" | "map-contains" | "map-len" | "map-keys" => {
+                    let (op, arity, usage) = match &head[..] {
+                        "map-get" => (MapOp::Get, 2, "(map-get map key)"),
+                        "map-contains" => (MapOp::Contains, 2, "(map-contains map key)"),
+                        "map-len" => (MapOp::Len, 1, "(map-len map)"),
+                        "map-keys" => (MapOp::Keys, 1, "(map-keys map)"),
+                        _ => return None,
+                    };
+                    guard!(error_log, subtree.len() == arity + 1, malformed(usage));
+                    let args_opt = into_syntax_list(error_log, &subtree[1..]);
+                    Some(SyntaxTree::MapOp(op, args_opt?))
+                }
+                "map-insert" | "map-remove" => {
+                    let (op, arity, usage) = match &head[..] {
+                        "map-insert" => (MapUpdate::Insert, 3, "(map-insert map key value)"),
+                        "map-remove" => (MapUpdate::Remove, 2, "(map-remove map key)"),
+                        _ => return None,
+                    };
+                    guard!(error_log, subtree.len() == arity + 1, malformed(usage));
+                    let map_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let map_opt = map_opt.and_then(|x| into_place(error_log, x));
+                    let args_opt = into_syntax_list(error_log, &subtree[2..]);
+                    Some(SyntaxTree::MapUpdate(op, map_opt?, args_opt?))
+                }
+                ":" => {
+                    guard!(error_log, subtree.len() == 3, malformed("(: value type)"));
+                    let val_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let type_opt = into_syntax_tree(error_log, &subtree[2]);
+                    Some(SyntaxTree::Ascribe(Box::new(val_opt?), Box::new(type_opt?)))
+                }
+                "fn" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(fn (param...) body)")
+                    );
+                    let params_opt = match_ok!(
+                        error_log,
+                        &subtree[1],
+                        TokenTree::Array(x) => x,
+                        malformed("(fn (param...) body)")
+                    );
+                    let params_opt = params_opt.and_then(|params| {
+                        let mut out_opt = Some(Vec::new());
+                        for it in params {
+                            let param_opt = match it {
+                                TokenTree::Atom(x) => Some((x.clone(), None)),
+                                TokenTree::Array(pair) if pair.len() == 2 => {
+                                    let name_opt = match_ok!(
+                                        error_log,
+                                        &pair[0],
+                                        TokenTree::Atom(x) => x.clone(),
+                                        malformed("(fn (param...) body)")
+                                    );
+                                    let type_opt = into_syntax_tree(error_log, &pair[1]);
+                                    name_opt.zip(type_opt).map(|(x, t)| (x, Some(t)))
+                                }
+                                _ => {
+                                    writeln!(error_log, "{}", malformed("(fn (param...) body)"))
+                                        .unwrap();
+                                    None
+                                }
+                            };
+                            match (&mut out_opt, param_opt) {
+                                (Some(out), Some(param)) => {
+                                    if out.iter().any(|(x, _)| *x == param.0) {
+                                        writeln!(error_log, "Duplicate parameter {:?}", param.0)
+                                            .unwrap();
+                                        out_opt = None;
+                                    } else {
+                                        out.push(param);
+                                    }
+                                }
+                                _ => out_opt = None,
+                            }
+                        }
+                        out_opt
+                    });
+                    let body_opt = into_syntax_tree(error_log, &subtree[2]);
+                    Some(SyntaxTree::Lambda(params_opt?, Box::new(body_opt?)))
+                }
+                "letrec" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 4,
+                        malformed("(letrec name (fn (param...) body) body)")
+                    );
+                    let var_opt = match_ok!(
+                        error_log,
+                        &subtree[1],
+                        TokenTree::Atom(x) => x.clone(),
+                        malformed("(letrec name (fn (param...) body) body)")
+                    );
+                    let val_opt = into_syntax_tree(error_log, &subtree[2]);
+                    let body_opt = into_syntax_tree(error_log, &subtree[3]);
+                    let val_opt = match val_opt {
+                        Some(SyntaxTree::Generic(_, ref x))
+                            if matches!(**x, SyntaxTree::Lambda(_, _)) =>
+                        {
+                            val_opt
+                        }
+                        _ => {
+                            match_ok!(
+                                error_log,
+                                val_opt,
+                                Some(x @ SyntaxTree::Lambda(_, _)) => x,
+                                malformed("(letrec name (fn (param...) body) body)")
+                            )
+                        }
+                    };
+                    Some(SyntaxTree::LetRec(
+                        var_opt?,
+                        Box::new(val_opt?),
+                        Box::new(body_opt?),
+                    ))
+                }
+                "generic" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(generic (param...) body)")
+                    );
+                    let params_opt = match_ok!(
+                        error_log,
+                        &subtree[1],
+                        TokenTree::Array(x) => x,
+                        malformed("(generic (param...) body)")
+                    );
+                    let params_opt = params_opt.and_then(|params| {
+                        let mut out: Vec<Rc<str>> = Vec::new();
+                        for it in params {
+                            let name = match_ok!(
+                                error_log,
+                                it,
+                                TokenTree::Atom(x) => x,
+                                malformed("(generic (param...) body)")
+                            )?;
+                            if out.contains(name) {
+                                writeln!(error_log, "Duplicate type parameter {name:?}").unwrap();
+                                return None;
+                            }
+                            out.push(name.clone());
+                        }
+                        Some(out)
+                    });
+                    let body_opt = into_syntax_tree(error_log, &subtree[2]);
+                    Some(SyntaxTree::Generic(params_opt?, Box::new(body_opt?)))
+                }
+                "fn-t" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 3,
+                        malformed("(fn-t (param...) type)")
+                    );
+                    let params_opt = match_ok!(
+                        error_log,
+                        &subtree[1],
+                        TokenTree::Array(x) => x,
+                        malformed("(fn-t (param...) type)")
+                    );
+                    let params_opt = params_opt.and_then(|x| into_syntax_list(error_log, x));
+                    let ret_opt = into_syntax_tree(error_log, &subtree[2]);
+                    Some(SyntaxTree::LiteralFuncType(params_opt?, Box::new(ret_opt?)))
+                }
+                "quote" => {
+                    guard!(error_log, subtree.len() == 2, malformed("(quote datum)"));
+                    Some(SyntaxTree::Quote(subtree[1].clone()))
+                }
+                "quasiquote" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 2,
+                        malformed("(quasiquote template)")
+                    );
+                    Some(SyntaxTree::Quasiquote(into_quasi(
+                        error_log,
+                        &subtree[1],
+                        0,
+                    )?))
+                }
+                "raise" | "throw" => {
+                    guard!(
+                        error_log,
+                        subtree.len() == 2,
+                        malformed(&format!("({head} value)"))
+                    );
+                    let val_opt = into_syntax_tree(error_log, &subtree[1]);
+                    Some(SyntaxTree::Raise(Box::new(val_opt?)))
+                }
+                "?" => {
+                    guard!(error_log, subtree.len() == 2, malformed("(? value)"));
+                    let val_opt = into_syntax_tree(error_log, &subtree[1]);
+                    Some(SyntaxTree::Propagate(Box::new(val_opt?)))
+                }
+                "try" => {
+                    guard!(
+                        error_log,
+                        subtree.len() > 2,
+                        malformed("(try body (catch ...)... [(finally expr)])")
+                    );
+                    let body_opt = into_syntax_tree(error_log, &subtree[1]);
+                    let mut clauses = &subtree[2..];
+                    let mut finally_opt = Some(None);
+                    if let Some(TokenTree::Array(last)) = clauses.last()
+                        && matches!(last.first(), Some(TokenTree::Atom(x)) if &x[..] == "finally")
+                    {
+                        clauses = &clauses[..clauses.len() - 1];
+                        finally_opt = match &last[..] {
+                            [_, x] => into_syntax_tree(error_log, x).map(|x| Some(Box::new(x))),
+                            _ => {
+                                writeln!(error_log, "Expected `(finally expr)` in `{tree1}`")
+                                    .unwrap();
+                                None
+                            }
+                        };
+                    }
+                    let mut out_opt = Some(Vec::new());
+                    for it in clauses {
+                        let catch_opt = into_catch(error_log, it);
+                        match (&mut out_opt, catch_opt) {
+                            (Some(out), Some(catch)) => out.push(catch),
+                            _ => out_opt = None,
+                        }
+                    }
+                    Some(SyntaxTree::Try(Box::new(body_opt?), out_opt?, finally_opt?))
+                }
+                "import" => {
+                    writeln!(
+                        error_log,
+                        "`import` is only allowed at the top of a program in `{tree1}`"
+                    )
+                    .unwrap();
+                    None
+                }
+                "deftest" => {
+                    writeln!(
+                        error_log,
+                        "`deftest` is only allowed in a module in `{tree1}`"
+                    )
+                    .unwrap();
                     None
                 }
+                "unquote" | "unquote-splicing" => {
+                    writeln!(error_log, "`{head}` outside of `quasiquote` in `{tree1}`").unwrap();
+                    None
+                }
+                // Registered forms, which cannot take the names of `forms::CORE_FORMS` above.
+                // The arity is checked when typing, as a local binding may shadow the form
+                _ if let Some(form) = forms::lookup(head) => {
+                    let args_opt = form.0.operands(error_log, &subtree[1..]);
+                    Some(SyntaxTree::Form(form, args_opt?))
+                }
+                _ => {
+                    let args_opt = into_syntax_list(error_log, &subtree[1..]);
+                    Some(SyntaxTree::Call(
+                        Box::new(SyntaxTree::Ident(head.clone())),
+                        args_opt?,
+                    ))
+                }
             }
         }
         TokenTree::Int64(x) => Some(SyntaxTree::LiteralInt64(*x)),
+        TokenTree::Str(x) => Some(SyntaxTree::LiteralStr(x.clone())),
     }
 }
 
 impl TryFrom<&TokenTree> for SyntaxTree {
     type Error = String;
     fn try_from(value: &TokenTree) -> Result<Self, Self::Error> {
+        let resolved = modules::resolve(value)?;
+        let expanded = macros::expand(&resolved)?;
         let mut error_log = String::new();
-        let tree2 = into_syntax_tree(&mut error_log, value);
+        let tree2 = into_syntax_tree(&mut error_log, &expanded);
         tree2.ok_or(error_log)
     }
 }
+
+fn write_list<T: Display>(f: &mut fmt::Formatter<'_>, head: &str, items: &[T]) -> fmt::Result {
+    write!(f, "({head}")?;
+    for it in items {
+        write!(f, " {it}")?;
+    }
+    write!(f, ")")
+}
+
+fn write_let_pattern(f: &mut fmt::Formatter<'_>, pattern: &Pattern) -> fmt::Result {
+    match pattern {
+        Pattern::Tuple(items) => {
+            write!(f, "(")?;
+            for (i, it) in items.iter().enumerate() {
+                if i > 0 {
+                    write!(f, " ")?;
+                }
+                write_let_pattern(f, it)?;
+            }
+            write!(f, ")")
+        }
+        _ => write!(f, "{pattern}"),
+    }
+}
+
+impl Display for ArithmeticOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Add => "+",
+            Self::Sub => "-",
+            Self::Mul => "*",
+            Self::Div => "/",
+            Self::Rem => "%",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for ArrayOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Push => "array-push",
+            Self::Pop => "array-pop",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for MapOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Get => "map-get",
+            Self::Contains => "map-contains",
+            Self::Len => "map-len",
+            Self::Keys => "map-keys",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for MapUpdate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let s = match self {
+            Self::Insert => "map-insert",
+            Self::Remove => "map-remove",
+        };
+        write!(f, "{s}")
+    }
+}
+
+impl Display for Place {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Var(x) => write!(f, "{x}"),
+            Self::Index(array, index) => write!(f, "(array-get {array} {index})"),
+            Self::Field(record, field) => write!(f, "(field-get {record} {field})"),
+        }
+    }
+}
+
+impl Display for Pattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Wildcard => write!(f, "_"),
+            Self::Bind(x) => write!(f, "{x}"),
+            Self::LiteralInt64(x) => write!(f, "{x}"),
+            Self::Variant(name, payload) => write_list(f, name, payload),
+            Self::Tuple(items) => write_list(f, "tuple", items),
+        }
+    }
+}
+
+// Renders the tree back as source, used to point at expressions in diagnostics
+impl Display for SyntaxTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Ident(x) => write!(f, "{x}"),
+            Self::LetVal(var, val, body) => write!(f, "(let {var} {val} {body})"),
+            Self::LetType(var, typ, body) => write!(f, "(var {var} {typ} {body})"),
+            Self::Seq(items) => write_list(f, "seq", items),
+            Self::Set(place, val) => write!(f, "(set {place} {val})"),
+            Self::CompoundSet(op, place, val) => write!(f, "({op}= {place} {val})"),
+            Self::LiteralInt64(x) => write!(f, "{x}"),
+            Self::LiteralArray(items) => write_list(f, "array", items),
+            Self::LiteralArrayType(inner, None) => write!(f, "(array-t {inner})"),
+            Self::LiteralArrayType(inner, Some(len)) => write!(f, "(array-t {inner} {len})"),
+            Self::Arithmetic(op, items) => write_list(f, &op.to_string(), items),
+            Self::ArrayGet(array, index) => write!(f, "(array-get {array} {index})"),
+            Self::LiteralStructType(fields) => {
+                write!(f, "(struct")?;
+                for (name, typ) in fields {
+                    write!(f, " ({name} {typ})")?;
+                }
+                write!(f, ")")
+            }
+            Self::LiteralStruct(typ, fields) => {
+                write!(f, "(record {typ}")?;
+                for (name, val) in fields {
+                    write!(f, " ({name} {val})")?;
+                }
+                write!(f, ")")
+            }
+            Self::FieldGet(record, field) => write!(f, "(field-get {record} {field})"),
+            Self::LiteralEnumType(variants) => {
+                write!(f, "(enum")?;
+                for (name, payload) in variants {
+                    write!(f, " ")?;
+                    write_list(f, name, payload)?;
+                }
+                write!(f, ")")
+            }
+            Self::LiteralVariant(typ, name, payload) => {
+                write!(f, "(variant {typ} {name}")?;
+                for it in payload {
+                    write!(f, " {it}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Match(val, arms) => {
+                write!(f, "(match {val}")?;
+                for (pattern, body) in arms {
+                    write!(f, " ({pattern} {body})")?;
+                }
+                write!(f, ")")
+            }
+            Self::LetPattern(pattern, val, body) => {
+                write!(f, "(let ")?;
+                write_let_pattern(f, pattern)?;
+                write!(f, " {val} {body})")
+            }
+            Self::LiteralTupleType(items) => write_list(f, "tuple-t", items),
+            Self::LiteralTuple(items) => write_list(f, "tuple", items),
+            Self::TupleGet(tuple, index) => write!(f, "(tuple-get {tuple} {index})"),
+            Self::LiteralStr(x) => write!(f, "{}", TokenTree::Str(x.clone())),
+            Self::LiteralMapType(key, val) => write!(f, "(map-t {key} {val})"),
+            Self::LiteralMap(items) => {
+                write!(f, "(map")?;
+                for (key, val) in items {
+                    write!(f, " ({key} {val})")?;
+                }
+                write!(f, ")")
+            }
+            Self::ArrayOp(op, array, args) => write_list(f, &format!("{op} {array}"), args),
+            Self::Form(form, args) => write_list(f, form.0.name(), args),
+            Self::MapOp(op, args) => write_list(f, &op.to_string(), args),
+            Self::MapUpdate(op, map, args) => write_list(f, &format!("{op} {map}"), args),
+            Self::Ascribe(val, typ) => write!(f, "(: {val} {typ})"),
+            Self::Lambda(params, body) => {
+                write!(f, "(fn (")?;
+                for (i, (name, typ)) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    match typ {
+                        None => write!(f, "{name}")?,
+                        Some(typ) => write!(f, "({name} {typ})")?,
+                    }
+                }
+                write!(f, ") {body})")
+            }
+            Self::LetRec(var, val, body) => write!(f, "(letrec {var} {val} {body})"),
+            Self::Generic(params, body) => {
+                write!(f, "(generic (")?;
+                for (i, it) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{it}")?;
+                }
+                write!(f, ") {body})")
+            }
+            Self::Call(func, args) => write_list(f, &func.to_string(), args),
+            Self::LiteralFuncType(params, ret) => {
+                write!(f, "(fn-t (")?;
+                for (i, it) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{it}")?;
+                }
+                write!(f, ") {ret})")
+            }
+            Self::Quote(x) => write!(f, "(quote {x})"),
+            Self::Quasiquote(x) => write!(f, "(quasiquote {x})"),
+            Self::Raise(x) => write!(f, "(raise {x})"),
+            Self::Propagate(x) => write!(f, "(? {x})"),
+            Self::Try(body, catches, finally) => {
+                write!(f, "(try {body}")?;
+                for it in catches {
+                    write!(f, " {it}")?;
+                }
+                if let Some(x) = finally {
+                    write!(f, " (finally {x})")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+impl Display for Catch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Any(handler) => write!(f, "(catch _ {handler})"),
+            Self::Typed(var, typ, handler) => write!(f, "(catch ({var} {typ}) {handler})"),
+        }
+    }
+}
+
+impl Display for Quasi {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Datum(x) => write!(f, "{x}"),
+            Self::List(items) => {
+                write!(f, "(")?;
+                for (i, it) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{it}")?;
+                }
+                write!(f, ")")
+            }
+            Self::Unquote(x) => write!(f, "(unquote {x})"),
+            Self::Splice(x) => write!(f, "(unquote-splicing {x})"),
+        }
+    }
+}
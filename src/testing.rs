@@ -0,0 +1,68 @@
+use crate::modules;
+use crate::syntax_tree::SyntaxTree;
+use crate::token_tree::{TokenTree, item_spans};
+use crate::typed_tree::{TypedTree, interpret_no_context};
+use std::fmt::Write;
+use std::rc::Rc;
+
+#[derive(Debug, Clone)]
+pub struct TestResult {
+    pub name: Rc<str>,
+    // Line and column of the `deftest`
+    pub span: (usize, usize),
+    pub outcome: Result<(), String>,
+}
+
+// Type checks and runs the test program, each one with a fresh runtime context
+fn run_test(program: &TokenTree) -> Result<(), String> {
+    let syntax_tree = SyntaxTree::try_from(program)?;
+    TypedTree::try_from(&syntax_tree)?;
+    interpret_no_context(program)?;
+    Ok(())
+}
+
+// Runs every `deftest` of the module source in order; `name` is the module's own name
+pub fn run_tests(name: &str, source: &str) -> Result<Vec<TestResult>, String> {
+    let tests = modules::tests(name, source)?;
+    let spans = item_spans(source)?;
+    let results = tests.iter().map(|x| TestResult {
+        name: x.name.clone(),
+        // The first item is the `module` head
+        span: spans[x.index + 1],
+        outcome: run_test(&x.program),
+    });
+    Ok(results.collect())
+}
+
+pub fn passed(results: &[TestResult]) -> bool {
+    results.iter().all(|x| x.outcome.is_ok())
+}
+
+// One line per test with the failure messages indented below it, then a summary line
+pub fn report(path: &str, results: &[TestResult]) -> String {
+    let mut out = String::new();
+    for it in results {
+        let (line, col) = it.span;
+        let status = if it.outcome.is_ok() { "ok" } else { "FAILED" };
+        writeln!(
+            &mut out,
+            "{path}:{line}:{col}: test {} ... {status}",
+            it.name
+        )
+        .unwrap();
+        if let Err(err) = &it.outcome {
+            for msg in err.trim_end().lines() {
+                writeln!(&mut out, "    {msg}").unwrap();
+            }
+        }
+    }
+    let failed = results.iter().filter(|x| x.outcome.is_err()).count();
+    let status = if failed == 0 { "ok" } else { "FAILED" };
+    writeln!(
+        &mut out,
+        "test result: {status}. {} passed; {failed} failed",
+        results.len() - failed
+    )
+    .unwrap();
+    out
+}
@@ -1,13 +1,14 @@
-use std::fmt::Write;
+use std::fmt::{self, Display, Write};
 use std::iter::Peekable;
 use std::rc::Rc;
 use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum TokenTree {
     Atom(Rc<str>),
     Array(Vec<TokenTree>),
     Int64(i64),
+    Str(Rc<str>),
 }
 
 fn skip_whitespace(iter: &mut Peekable<impl Iterator<Item = (usize, usize, char)>>) {
@@ -27,13 +28,39 @@ fn next_word(iter: &mut Peekable<impl Iterator<Item = (usize, usize, char)>>) ->
     out
 }
 
+fn parse_string(
+    err_log: &mut String,
+    iter: &mut Peekable<impl Iterator<Item = (usize, usize, char)>>,
+) -> Option<String> {
+    let (line1, col1, _) = iter.next()?;
+    let mut out = String::new();
+    loop {
+        match iter.next() {
+            None => {
+                writeln!(err_log, "Unexpected EOF").unwrap();
+                writeln!(err_log, "Unterminated string at {line1}:{col1}").unwrap();
+                return None;
+            }
+            Some((_, _, '"')) => return Some(out),
+            Some((line, col, '\\')) => match iter.next() {
+                Some((_, _, 'n')) => out.push('\n'),
+                Some((_, _, 't')) => out.push('\t'),
+                Some((_, _, c @ ('"' | '\\'))) => out.push(c),
+                _ => {
+                    writeln!(err_log, "Unknown escape sequence at {line}:{col}").unwrap();
+                    return None;
+                }
+            },
+            Some((_, _, c)) => out.push(c),
+        }
+    }
+}
+
 fn parse_array(
     err_log: &mut String,
     iter: &mut Peekable<impl Iterator<Item = (usize, usize, char)>>,
 ) -> Option<Vec<TokenTree>> {
-    let Some((line1, col1, '(')) = iter.next() else {
-        panic!()
-    };
+    let (line1, col1, _) = iter.next()?;
     let mut out = Some(Vec::new());
     loop {
         skip_whitespace(iter);
@@ -59,6 +86,24 @@ fn parse_array(
     }
 }
 
+// `'x`, `` `x ``, `,x` and `,@x` read as `(quote x)`, `(quasiquote x)`, `(unquote x)` and
+// `(unquote-splicing x)`
+fn parse_quoted(
+    err_log: &mut String,
+    iter: &mut Peekable<impl Iterator<Item = (usize, usize, char)>>,
+) -> Option<TokenTree> {
+    let (_, _, c) = iter.next()?;
+    let head = match c {
+        '\'' => "quote",
+        '`' => "quasiquote",
+        _ if iter.next_if(|&(_, _, c)| c == '@').is_some() => "unquote-splicing",
+        _ => "unquote",
+    };
+    skip_whitespace(iter);
+    let x = parse_token_tree(err_log, iter)?;
+    Some(TokenTree::Array(vec![TokenTree::Atom(head.into()), x]))
+}
+
 fn parse_token_tree(
     err_log: &mut String,
     iter: &mut Peekable<impl Iterator<Item = (usize, usize, char)>>,
@@ -73,6 +118,8 @@ fn parse_token_tree(
             None
         }
         Some((_, _, '(')) => parse_array(err_log, iter).map(TokenTree::Array),
+        Some((_, _, '"')) => parse_string(err_log, iter).map(|x| TokenTree::Str(x.into())),
+        Some((_, _, '\'' | '`' | ',')) => parse_quoted(err_log, iter),
         Some(_) => {
             let word = next_word(iter);
             if let Ok(x) = word.parse::<i64>() {
@@ -84,14 +131,69 @@ fn parse_token_tree(
     }
 }
 
+impl Display for TokenTree {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Atom(x) => write!(f, "{x}"),
+            Self::Int64(x) => write!(f, "{x}"),
+            Self::Str(x) => {
+                write!(f, "\"")?;
+                for c in x.chars() {
+                    match c {
+                        '\n' => write!(f, "\\n")?,
+                        '\t' => write!(f, "\\t")?,
+                        '"' | '\\' => write!(f, "\\{c}")?,
+                        _ => write!(f, "{c}")?,
+                    }
+                }
+                write!(f, "\"")
+            }
+            Self::Array(items) => {
+                write!(f, "(")?;
+                for (i, it) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{it}")?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+fn chars(s: &str) -> Peekable<impl Iterator<Item = (usize, usize, char)>> {
+    s.split_inclusive('\n')
+        .enumerate()
+        .flat_map(|(line, s)| s.char_indices().map(move |(col, c)| (line + 1, col + 1, c)))
+        .peekable()
+}
+
+// Line and column where each item of the top-level list starts
+pub fn item_spans(s: &str) -> Result<Vec<(usize, usize)>, String> {
+    let mut iter = chars(s);
+    skip_whitespace(&mut iter);
+    if iter.next_if(|&(_, _, c)| c == '(').is_none() {
+        return Err("Expected a list\n".into());
+    }
+    let mut err_log = String::new();
+    let mut out = Vec::new();
+    loop {
+        skip_whitespace(&mut iter);
+        match iter.peek() {
+            None | Some((_, _, ')')) => return Ok(out),
+            Some(&(line, col, _)) => out.push((line, col)),
+        }
+        if parse_token_tree(&mut err_log, &mut iter).is_none() {
+            return Err(err_log);
+        }
+    }
+}
+
 impl FromStr for TokenTree {
     type Err = String;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut iter = s
-            .split_inclusive('\n')
-            .enumerate()
-            .flat_map(|(line, s)| s.char_indices().map(move |(col, c)| (line + 1, col + 1, c)))
-            .peekable();
+        let mut iter = chars(s);
         skip_whitespace(&mut iter);
         let mut err_log = String::new();
         match parse_token_tree(&mut err_log, &mut iter) {
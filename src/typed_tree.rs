@@ -1,20 +1,80 @@
-use crate::syntax_tree::{ArithmeticOp, SyntaxTree};
+use crate::forms::{self, Form};
+use crate::macros;
+use crate::modules;
+use crate::pattern::{TypedPattern, missing_patterns, redundant_arms};
+use crate::syntax_tree::{
+    ArithmeticOp, ArrayOp, Catch, MapOp, MapUpdate, Pattern, Place, Quasi, SyntaxTree,
+};
 use crate::token_tree::TokenTree;
 use crate::util::insert_or_remove;
-use crate::{guard, match_ok};
+use crate::value_map::ValueMap;
+use crate::{guard, guard_opt, match_ok};
+use std::cmp::Ordering;
 use std::collections::HashMap;
-use std::fmt::Write;
+use std::fmt::{self, Display, Write};
+use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
 #[derive(Debug, Clone, Default)]
 pub struct TypeContext<'a> {
     pub error_log: String,
     pub variables: HashMap<&'a str, Option<TypeInfo>>,
+    pub declarations: HashMap<&'a str, Declaration<'a>>,
+    pub type_count: usize,
+    pub substitution: Vec<Option<TypeInfo>>,
+    // Return types of the enclosing functions, innermost last, for `?`
+    pub return_types: Vec<TypeInfo>,
+    // `?` on operands of still unknown type, checked once their function is typed
+    pub propagates: Vec<Propagate<'a>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Propagate<'a> {
+    tree: &'a SyntaxTree,
+    val_s: &'a SyntaxTree,
+    val_t: TypeInfo,
+    out_t: TypeInfo,
+    ret: TypeInfo,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Declaration<'a> {
+    // Immutable binding, pointing at the form that declared it
+    Let(&'a SyntaxTree),
+    // `var` declared inside this many functions; closures capture a copy of it, so only
+    // code in the same function can assign to it
+    Var(usize),
 }
 
 #[derive(Debug, Clone, Default)]
 pub struct RuntimeContext<'a> {
     pub variables: HashMap<&'a str, Value>,
+    pub type_count: usize,
+    // Value of the `raise` whose error message is propagating, taken by the `try` that catches it
+    pub raised: Option<Value>,
+    // Value a `?` returns early from the current function, propagated like an error until then
+    pub returning: Option<Value>,
+}
+
+// Ids of the built-in generic enums, user-defined types are numbered after them
+pub const OPTION_ID: usize = 0;
+pub const RESULT_ID: usize = 1;
+const BUILTIN_TYPE_COUNT: usize = 2;
+
+pub fn option_type(t: TypeInfo) -> TypeInfo {
+    TypeInfo::Enum(Rc::new(EnumType {
+        id: OPTION_ID,
+        params: vec![t.clone()],
+        variants: vec![("None".into(), vec![]), ("Some".into(), vec![t])],
+    }))
+}
+
+pub fn result_type(t: TypeInfo, e: TypeInfo) -> TypeInfo {
+    TypeInfo::Enum(Rc::new(EnumType {
+        id: RESULT_ID,
+        params: vec![t.clone(), e.clone()],
+        variants: vec![("Ok".into(), vec![t]), ("Err".into(), vec![e])],
+    }))
 }
 
 #[derive(Debug, PartialEq, Eq, Clone, Default)]
@@ -23,33 +83,266 @@ pub enum TypeInfo {
     Unit,
     Type(Box<TypeInfo>),
     Int64,
-    Array(Box<TypeInfo>),
+    // Arrays of unknown length have `None` as the length
+    Array(Box<TypeInfo>, Option<usize>),
+    Struct(Rc<StructType>),
+    Enum(Rc<EnumType>),
+    Tuple(Vec<TypeInfo>),
+    Str,
+    Map(Box<TypeInfo>, Box<TypeInfo>),
+    Var(usize),
+    Func(Vec<TypeInfo>, Box<TypeInfo>),
+    // Type scheme of a let-bound polymorphic value, never nested inside other types
+    Forall(Vec<usize>, Box<TypeInfo>),
+    // Quoted code: a symbol, an integer, a string or a list of these
+    Sexp,
 }
 
-#[derive(Debug, Clone, Default)]
+// Structs are compared nominally: every evaluation of a `struct` form gets a fresh id.
+// Generic structs also carry their type arguments, which the fields are expressed in
+#[derive(Debug, Clone)]
+pub struct StructType {
+    pub id: usize,
+    pub params: Vec<TypeInfo>,
+    pub fields: Vec<(Rc<str>, TypeInfo)>,
+}
+
+// Enums share the id counter with structs and are compared nominally as well
+#[derive(Debug, Clone)]
+pub struct EnumType {
+    pub id: usize,
+    pub params: Vec<TypeInfo>,
+    pub variants: Vec<(Rc<str>, Vec<TypeInfo>)>,
+}
+
+#[derive(Debug, Eq, Clone, Default)]
 pub enum Value {
     #[default]
     Unit,
     Int64(i64),
     Type(TypeInfo),
-    Array(Vec<Value>),
+    // Shared between copies, cloned only when a shared array gets mutated
+    Array(Rc<Vec<Value>>),
+    Struct(Rc<StructType>, Vec<Value>),
+    Enum(Rc<EnumType>, usize, Vec<Value>),
+    Tuple(Vec<Value>),
+    Str(Rc<str>),
+    Map(ValueMap),
+    Func(Rc<Closure>),
+    Symbol(Rc<str>),
+    List(Rc<Vec<Value>>),
+}
+
+// Captures only the variables its body mentions; `name` lets `letrec` functions see themselves
+#[derive(Clone)]
+pub struct Closure {
+    pub name: Option<Rc<str>>,
+    pub params: Vec<Rc<str>>,
+    pub body: TokenTree,
+    pub env: Vec<(Rc<str>, Value)>,
 }
 
 #[derive(Debug, Clone)]
 pub struct TypedTree(TypeInfo, TypedOp);
 
+impl TypedTree {
+    pub fn type_info(&self) -> &TypeInfo {
+        &self.0
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum TypedOp {
     Const(Value),
     LocalVar(usize, Rc<str>, Box<TypedTree>, Box<TypedTree>),
     LocalGet(usize, Rc<str>),
-    LocalSet(usize, Rc<str>, Box<TypedTree>),
+    Set(TypedPlace, Box<TypedTree>),
+    CompoundSet(ArithmeticOp, TypedPlace, Box<TypedTree>),
     Arithmetic(ArithmeticOp, Vec<TypedTree>),
     Seq(Vec<TypedTree>),
     Array(Vec<TypedOp>),
-    ArrayT(Box<TypedTree>),
+    ArrayT(Box<TypedTree>, Option<usize>),
     ArrayGet(Box<TypedOp>, Box<TypedOp>),
-    ArraySet(Box<TypedOp>, Box<TypedOp>, Box<TypedTree>),
+    ArrayOp(ArrayOp, TypedPlace, Vec<TypedTree>),
+    Form(Form, Vec<TypedTree>),
+    StructT(Vec<TypedTree>),
+    Struct(Vec<TypedTree>),
+    FieldGet(Box<TypedOp>, usize),
+    EnumT(Vec<Vec<TypedTree>>),
+    Variant(usize, Vec<TypedTree>),
+    Match(Box<TypedTree>, Vec<(TypedPattern, TypedTree)>),
+    Destructure(TypedPattern, Box<TypedTree>, Box<TypedTree>),
+    TupleT(Vec<TypedTree>),
+    Tuple(Vec<TypedTree>),
+    TupleGet(Box<TypedOp>, usize),
+    MapT(Box<TypedTree>, Box<TypedTree>),
+    Map(Vec<(TypedTree, TypedTree)>),
+    MapOp(MapOp, Vec<TypedTree>),
+    MapUpdate(MapUpdate, TypedPlace, Vec<TypedTree>),
+    FuncT(Vec<TypedTree>, Box<TypedTree>),
+    Lambda(Vec<Rc<str>>, Box<TypedTree>),
+    LetRec(Rc<str>, Box<TypedTree>, Box<TypedTree>),
+    Call(Box<TypedTree>, Vec<TypedTree>),
+    Quasiquote(TypedQuasi),
+    Raise(Box<TypedTree>),
+    Try(Box<TypedTree>, Vec<TypedCatch>, Option<Box<TypedTree>>),
+    Propagate(Box<TypedTree>),
+}
+
+#[derive(Debug, Clone)]
+pub enum TypedCatch {
+    Any(TypedTree),
+    Typed(Rc<str>, TypedTree, TypedTree),
+}
+
+#[derive(Debug, Clone)]
+pub enum TypedQuasi {
+    Datum(Value),
+    List(Vec<TypedQuasi>),
+    Unquote(Box<TypedTree>),
+    Splice(Box<TypedTree>),
+}
+
+#[derive(Debug, Clone)]
+pub enum TypedPlace {
+    Local(usize, Rc<str>),
+    Index(Box<TypedPlace>, Box<TypedTree>),
+    Field(Box<TypedPlace>, usize),
+}
+
+// Only hashable values can be map keys, but every value gets a hash consistent with `Eq`
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Unit | Self::Type(_) | Self::Map(_) | Self::Func(_) => {}
+            Self::Int64(x) => x.hash(state),
+            Self::Str(x) | Self::Symbol(x) => x.hash(state),
+            Self::Array(items) | Self::List(items) => items.hash(state),
+            Self::Tuple(items) => items.hash(state),
+            Self::Struct(t, items) => {
+                t.id.hash(state);
+                items.hash(state);
+            }
+            Self::Enum(t, i, items) => {
+                t.id.hash(state);
+                i.hash(state);
+                items.hash(state);
+            }
+        }
+    }
+}
+
+// Like `Hash`, nominal values compare by type id, as the type parameters they were built
+// with may still be variables
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Unit, Self::Unit) => true,
+            (Self::Int64(x), Self::Int64(y)) => x == y,
+            (Self::Type(x), Self::Type(y)) => x == y,
+            (Self::Array(xs), Self::Array(ys)) | (Self::List(xs), Self::List(ys)) => xs == ys,
+            (Self::Tuple(xs), Self::Tuple(ys)) => xs == ys,
+            (Self::Str(x), Self::Str(y)) | (Self::Symbol(x), Self::Symbol(y)) => x == y,
+            (Self::Map(x), Self::Map(y)) => x == y,
+            (Self::Func(x), Self::Func(y)) => x == y,
+            (Self::Struct(t, xs), Self::Struct(u, ys)) => t.id == u.id && xs == ys,
+            (Self::Enum(t, i, xs), Self::Enum(u, j, ys)) => (t.id, i) == (u.id, j) && xs == ys,
+            _ => false,
+        }
+    }
+}
+
+fn write_values(f: &mut fmt::Formatter<'_>, head: &str, items: &[Value]) -> fmt::Result {
+    write!(f, "({head}")?;
+    for it in items {
+        write!(f, " {it}")?;
+    }
+    write!(f, ")")
+}
+
+// Same syntax as the expressions building the value, where there is one
+impl Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "unit"),
+            Self::Int64(x) => write!(f, "{x}"),
+            Self::Type(t) => write!(f, "{t}"),
+            Self::Array(items) => write_values(f, "array", items),
+            Self::Tuple(items) => write_values(f, "tuple", items),
+            Self::Str(x) => write!(f, "{}", TokenTree::Str(x.clone())),
+            Self::Struct(t, items) => {
+                write!(f, "(record struct#{}", t.id)?;
+                for ((name, _), it) in t.fields.iter().zip(items) {
+                    write!(f, " ({name} {it})")?;
+                }
+                write!(f, ")")
+            }
+            Self::Enum(t, i, items) => write_values(f, &t.variants[*i].0, items),
+            Self::Map(map) => {
+                write!(f, "(map")?;
+                for (k, v) in map.iter() {
+                    write!(f, " ({k} {v})")?;
+                }
+                write!(f, ")")
+            }
+            Self::Func(_) => write!(f, "(fn ...)"),
+            Self::Symbol(_) | Self::List(_) => match unquote_value(self) {
+                Ok(x) => write!(f, "'{x}"),
+                Err(_) => write!(f, "{self:?}"),
+            },
+        }
+    }
+}
+
+// Functions are only equal to themselves
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for Closure {}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "(fn (")?;
+        for (i, it) in self.params.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{it}")?;
+        }
+        write!(f, ") ...)")
+    }
+}
+
+impl PartialEq for StructType {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for StructType {}
+
+impl StructType {
+    pub fn field_index(&self, name: &str) -> Option<usize> {
+        self.fields.iter().position(|(x, _)| &x[..] == name)
+    }
+}
+
+impl PartialEq for EnumType {
+    fn eq(&self, other: &Self) -> bool {
+        self.id == other.id
+    }
+}
+
+impl Eq for EnumType {}
+
+impl EnumType {
+    pub fn variant_index(&self, name: &str) -> Option<usize> {
+        self.variants.iter().position(|(x, _)| &x[..] == name)
+    }
 }
 
 impl TypeInfo {
@@ -58,51 +351,325 @@ impl TypeInfo {
             Self::Unit => Value::Unit,
             Self::Type(_) => Value::Type(TypeInfo::Unit),
             Self::Int64 => Value::Int64(0),
-            Self::Array(_) => Value::Array(Vec::new()),
+            Self::Array(t, len) => Value::Array(Rc::new(vec![t.zero(); len.unwrap_or(0)])),
+            Self::Struct(s) => {
+                Value::Struct(s.clone(), s.fields.iter().map(|x| x.1.zero()).collect())
+            }
+            Self::Enum(e) => Value::Enum(
+                e.clone(),
+                0,
+                e.variants[0].1.iter().map(|x| x.zero()).collect(),
+            ),
+            Self::Tuple(items) => Value::Tuple(items.iter().map(|x| x.zero()).collect()),
+            Self::Str => Value::Str("".into()),
+            Self::Map(_, _) => Value::Map(ValueMap::default()),
+            Self::Sexp => Value::List(Rc::default()),
+            Self::Var(_) | Self::Func(_, _) | Self::Forall(_, _) => Value::Unit,
+        }
+    }
+
+    // Functions have no sensible default, so `var` rejects types containing them
+    pub fn has_zero(&self) -> bool {
+        match self {
+            Self::Struct(s) => s.fields.iter().all(|x| x.1.has_zero()),
+            Self::Enum(e) => e.variants[0].1.iter().all(|x| x.has_zero()),
+            Self::Tuple(items) => items.iter().all(|x| x.has_zero()),
+            Self::Var(_) | Self::Func(_, _) | Self::Forall(_, _) => false,
+            _ => true,
+        }
+    }
+
+    pub fn is_hashable(&self) -> bool {
+        match self {
+            // Unresolved key types are accepted optimistically
+            Self::Unit | Self::Int64 | Self::Str | Self::Sexp | Self::Var(_) => true,
+            Self::Tuple(items) => items.iter().all(|x| x.is_hashable()),
+            _ => false,
+        }
+    }
+
+    pub fn is_ordered(&self) -> bool {
+        match self {
+            Self::Int64 | Self::Str | Self::Var(_) => true,
+            Self::Array(t, _) => t.is_ordered(),
+            Self::Tuple(items) => items.iter().all(|x| x.is_ordered()),
+            _ => false,
         }
     }
 }
 
-pub fn into_typed_tree<'a>(ctx: &mut TypeContext<'a>, tree: &'a SyntaxTree) -> Option<TypedTree> {
-    match tree {
-        SyntaxTree::Ident(var) => match ctx.variables.get(&var[..]) {
-            None => {
-                writeln!(&mut ctx.error_log, "Unknown variable {var}").unwrap();
-                None
+fn write_types(f: &mut fmt::Formatter<'_>, head: &str, items: &[TypeInfo]) -> fmt::Result {
+    write!(f, "({head}")?;
+    for it in items {
+        write!(f, " {it}")?;
+    }
+    write!(f, ")")
+}
+
+// Same syntax as the type expressions in source, nominal types are shown with their id
+impl Display for TypeInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unit => write!(f, "unit"),
+            Self::Type(t) => write!(f, "(type {t})"),
+            Self::Int64 => write!(f, "i64"),
+            Self::Array(t, None) => write!(f, "(array-t {t})"),
+            Self::Array(t, Some(len)) => write!(f, "(array-t {t} {len})"),
+            Self::Struct(s) => {
+                write!(f, "(struct#{}", s.id)?;
+                for (name, t) in &s.fields {
+                    write!(f, " ({name} {t})")?;
+                }
+                write!(f, ")")
             }
-            Some(None) => {
-                // Errors about variable types do not make noise as errors about missing variables
-                None
+            Self::Enum(e) if e.id == OPTION_ID => write_types(f, "Option", &e.params),
+            Self::Enum(e) if e.id == RESULT_ID => write_types(f, "Result", &e.params),
+            Self::Enum(e) => {
+                write!(f, "(enum#{}", e.id)?;
+                for (name, payload) in &e.variants {
+                    write!(f, " ")?;
+                    write_types(f, name, payload)?;
+                }
+                write!(f, ")")
             }
-            Some(Some(found)) => Some(TypedTree(found.clone(), TypedOp::LocalGet(0, var.clone()))),
-        },
+            Self::Tuple(items) => write_types(f, "tuple-t", items),
+            Self::Str => write!(f, "str"),
+            Self::Sexp => write!(f, "sexp"),
+            Self::Map(k, v) => write!(f, "(map-t {k} {v})"),
+            Self::Var(x) => write!(f, "?{x}"),
+            Self::Func(params, ret) => {
+                write!(f, "(fn-t (")?;
+                for (i, it) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "{it}")?;
+                }
+                write!(f, ") {ret})")
+            }
+            Self::Forall(vars, t) => {
+                write!(f, "(forall (")?;
+                for (i, x) in vars.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "?{x}")?;
+                }
+                write!(f, ") {t})")
+            }
+        }
+    }
+}
+
+pub fn into_typed_tree<'a>(ctx: &mut TypeContext<'a>, tree: &'a SyntaxTree) -> Option<TypedTree> {
+    into_typed_tree_expected(ctx, tree, None)
+}
+
+pub fn check_typed_tree<'a>(
+    ctx: &mut TypeContext<'a>,
+    tree: &'a SyntaxTree,
+    expected: &TypeInfo,
+) -> Option<TypedTree> {
+    let out = into_typed_tree_expected(ctx, tree, Some(expected))?;
+    unify_at(ctx, tree, expected, &out.0)?;
+    Some(out)
+}
+
+// Resolved type of the operand of `?`, which takes its kind from the return type when unknown
+fn propagate_kind(ctx: &mut TypeContext<'_>, p: &Propagate<'_>) -> TypeInfo {
+    let val_t = ctx.resolve(&p.val_t);
+    if let (TypeInfo::Var(_), TypeInfo::Enum(e)) = (&val_t, ctx.resolve(&p.ret)) {
+        let kind = match e.id {
+            OPTION_ID => Some(option_type(ctx.fresh_var())),
+            RESULT_ID => Some(result_type(ctx.fresh_var(), e.params[1].clone())),
+            _ => None,
+        };
+        if let Some(kind) = kind {
+            ctx.unify(&kind, &val_t);
+            return ctx.resolve(&val_t);
+        }
+    }
+    val_t
+}
+
+fn check_propagate(ctx: &mut TypeContext<'_>, p: &Propagate<'_>) -> Option<()> {
+    let Propagate {
+        tree, val_s, ret, ..
+    } = p;
+    let val_t = propagate_kind(ctx, p);
+    let (inner_t, ret_kind) = match &val_t {
+        TypeInfo::Enum(e) if e.id == OPTION_ID => {
+            (e.params[0].clone(), option_type(ctx.fresh_var()))
+        }
+        TypeInfo::Enum(e) if e.id == RESULT_ID => {
+            let ret_kind = result_type(ctx.fresh_var(), e.params[1].clone());
+            (e.params[0].clone(), ret_kind)
+        }
+        TypeInfo::Var(_) => {
+            writeln!(
+                &mut ctx.error_log,
+                "Cannot tell whether `{val_s}` is an Option or a Result in `{tree}`, annotate its type"
+            )
+            .unwrap();
+            return None;
+        }
+        _ => {
+            writeln!(
+                &mut ctx.error_log,
+                "Type mismatch in `{val_s}`: expected an Option or a Result, found {val_t}"
+            )
+            .unwrap();
+            return None;
+        }
+    };
+    if !ctx.unify(ret, &ret_kind) {
+        let ret = ctx.resolve(ret);
+        writeln!(
+            &mut ctx.error_log,
+            "Cannot use `{tree}` on {val_t} in a function returning {ret}"
+        )
+        .unwrap();
+        return None;
+    }
+    unify_at(ctx, tree, &p.out_t, &inner_t)
+}
+
+// Reports the conflict against the expression whose type had to match
+fn unify_at(
+    ctx: &mut TypeContext<'_>,
+    tree: &impl Display,
+    expected: &TypeInfo,
+    found: &TypeInfo,
+) -> Option<()> {
+    let expected_resolved = ctx.resolve(expected);
+    let found_resolved = ctx.resolve(found);
+    if ctx.unify(expected, found) {
+        return Some(());
+    }
+    let mut vars = Vec::new();
+    if let TypeInfo::Var(x) = expected_resolved {
+        found_resolved.free_vars(&mut vars);
+        if vars.contains(&x) {
+            writeln!(
+                &mut ctx.error_log,
+                "Infinite type in `{tree}`: {expected_resolved} occurs in {found_resolved}"
+            )
+            .unwrap();
+            return None;
+        }
+    }
+    writeln!(
+        &mut ctx.error_log,
+        "Type mismatch in `{tree}`: expected {expected_resolved}, found {found_resolved}"
+    )
+    .unwrap();
+    None
+}
+
+fn check_or_infer<'a>(
+    ctx: &mut TypeContext<'a>,
+    tree: &'a SyntaxTree,
+    expected: Option<&TypeInfo>,
+) -> Option<TypedTree> {
+    match expected {
+        Some(t) => check_typed_tree(ctx, tree, t),
+        None => into_typed_tree(ctx, tree),
+    }
+}
+
+// Type of a value that comes from one of several branches or items. Without an expected type
+// the first branch is inferred and the others are checked against its type with array lengths
+// erased; the lengths are kept only when every branch agrees on them
+struct Join {
+    hint: Option<TypeInfo>,
+    expected: bool,
+    types: Vec<TypeInfo>,
+}
+
+impl Join {
+    // An expected type that is still a variable is no hint, as the first branch would fix
+    // the lengths of all the others
+    fn new(ctx: &TypeContext<'_>, expected: Option<&TypeInfo>) -> Self {
+        let hint = expected
+            .map(|t| ctx.resolve(t))
+            .filter(|t| !matches!(t, TypeInfo::Var(_)));
+        Self {
+            expected: hint.is_some(),
+            hint,
+            types: Vec::new(),
+        }
+    }
+
+    fn hint(&self) -> Option<&TypeInfo> {
+        self.hint.as_ref()
+    }
+
+    fn add(&mut self, ctx: &TypeContext<'_>, t: &TypeInfo) {
+        if self.hint.is_none() {
+            self.hint = Some(ctx.resolve(t).erase_lengths());
+        }
+        self.types.push(t.clone());
+    }
+
+    fn finish(self, ctx: &TypeContext<'_>) -> Option<TypeInfo> {
+        let types: Vec<_> = self.types.iter().map(|t| ctx.resolve(t)).collect();
+        match types.first() {
+            Some(first) if !self.expected && types.iter().all(|t| t == first) => {
+                Some(first.clone())
+            }
+            _ => self.hint.map(|t| ctx.resolve(&t)),
+        }
+    }
+}
+
+// The expected type is only a hint pushed down into literals and branches;
+// `check_typed_tree` is responsible for comparing it with the result
+fn into_typed_tree_expected<'a>(
+    ctx: &mut TypeContext<'a>,
+    tree: &'a SyntaxTree,
+    expected: Option<&TypeInfo>,
+) -> Option<TypedTree> {
+    let expected = expected.map(|t| ctx.resolve(t));
+    let expected = expected.as_ref();
+    match tree {
+        SyntaxTree::Ident(var) => typed_local(ctx, var),
         SyntaxTree::LetVal(var, val, body) => {
-            let (var_type_opt, val_opt) = match into_typed_tree(ctx, val) {
-                None => (None, None),
-                Some(x) => (Some(x.0), Some(x.1)),
-            };
-            let old_type = ctx.variables.insert(var, var_type_opt);
-            let body_opt = into_typed_tree(ctx, body);
-            let var_type_opt = insert_or_remove(&mut ctx.variables, var, old_type);
+            let val_opt = into_typed_tree(ctx, val);
+            // Only syntactic values are generalized, anything else could be mutated later
+            let var_type_opt = val_opt.as_ref().map(|x| match is_syntactic_value(val) {
+                true => ctx.generalize(&x.0),
+                false => x.0.clone(),
+            });
+            let old = bind(ctx, var, var_type_opt, Declaration::Let(tree));
+            let body_opt = into_typed_tree_expected(ctx, body, expected);
+            unbind(ctx, var, old);
             let body = body_opt?;
             Some(TypedTree(
                 body.0.clone(),
-                TypedOp::LocalVar(
-                    0,
-                    var.clone(),
-                    Box::new(TypedTree(var_type_opt??, val_opt?)),
-                    Box::new(body),
-                ),
+                TypedOp::LocalVar(0, var.clone(), Box::new(val_opt?), Box::new(body)),
             ))
         }
         SyntaxTree::LetType(var, val, body) => {
-            let val_opt = into_typed_tree(ctx, val);
-            let var_type_opt =
-                match_ok!(&mut ctx.error_log, val_opt, Some(TypedTree(TypeInfo::Type(t), _)) => *t);
-            let old_type = ctx.variables.insert(var, var_type_opt);
-            let body_opt = into_typed_tree(ctx, body);
-            let var_type_opt = insert_or_remove(&mut ctx.variables, var, old_type);
-            let var_type = var_type_opt??;
+            let var_type_opt = into_type_expr(ctx, val).map(|x| x.0);
+            let depth = ctx.return_types.len();
+            let old = bind(ctx, var, var_type_opt, Declaration::Var(depth));
+            let body_opt = into_typed_tree_expected(ctx, body, expected);
+            let var_type_opt = unbind(ctx, var, old);
+            let var_type = ctx.resolve(&var_type_opt??);
+            let mut vars = Vec::new();
+            var_type.free_vars(&mut vars);
+            if !vars.is_empty() {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Type `{val}` is not known at compile time, found {var_type}"
+                )
+                .unwrap();
+                return None;
+            }
+            if !var_type.has_zero() {
+                writeln!(&mut ctx.error_log, "Type {var_type} has no default value").unwrap();
+                return None;
+            }
             let zero = var_type.zero();
             let body = body_opt?;
             Some(TypedTree(
@@ -117,8 +684,12 @@ pub fn into_typed_tree<'a>(ctx: &mut TypeContext<'a>, tree: &'a SyntaxTree) -> O
         }
         SyntaxTree::Seq(items) => {
             let mut out_opt = Some(Vec::new());
-            for it in items {
-                let item = into_typed_tree(ctx, it);
+            for (i, it) in items.iter().enumerate() {
+                let item = if i + 1 == items.len() {
+                    into_typed_tree_expected(ctx, it, expected)
+                } else {
+                    into_typed_tree(ctx, it)
+                };
                 match (&mut out_opt, item) {
                     (Some(out), Some(item)) => out.push(item),
                     _ => out_opt = None,
@@ -127,164 +698,1498 @@ pub fn into_typed_tree<'a>(ctx: &mut TypeContext<'a>, tree: &'a SyntaxTree) -> O
             let out = out_opt?;
             Some(TypedTree(out.last()?.0.clone(), TypedOp::Seq(out)))
         }
-        SyntaxTree::Set(var, val) => {
-            let val = into_typed_tree(ctx, val)?;
-            let var_type =
-                match_ok!(&mut ctx.error_log, ctx.variables.get(&var[..]), Some(x) => x)?
-                    .as_ref()?;
-            guard!(&mut ctx.error_log, var_type.eq(&val.0));
+        SyntaxTree::Set(place, val) => {
+            let place_opt = into_typed_place(ctx, place);
+            let mutable_opt = check_mutable(ctx, tree, place.root());
+            let val = check_or_infer(ctx, val, place_opt.as_ref().map(|x| &x.0))?;
+            let (_, place) = place_opt?;
+            mutable_opt?;
+            Some(TypedTree(
+                TypeInfo::Unit,
+                TypedOp::Set(place, Box::new(val)),
+            ))
+        }
+        SyntaxTree::CompoundSet(op, place_s, val) => {
+            let place_opt = into_typed_place(ctx, place_s);
+            let place_opt = place_opt.and_then(|(place_t, place)| {
+                unify_at(ctx, place_s, &TypeInfo::Int64, &place_t)?;
+                Some(place)
+            });
+            let mutable_opt = check_mutable(ctx, tree, place_s.root());
+            let val = check_typed_tree(ctx, val, &TypeInfo::Int64)?;
+            let place = place_opt?;
+            mutable_opt?;
             Some(TypedTree(
                 TypeInfo::Unit,
-                TypedOp::LocalSet(0, var.clone(), Box::new(val)),
+                TypedOp::CompoundSet(*op, place, Box::new(val)),
             ))
         }
         SyntaxTree::LiteralInt64(x) => {
             Some(TypedTree(TypeInfo::Int64, TypedOp::Const(Value::Int64(*x))))
         }
         SyntaxTree::LiteralArray(items) => {
+            let item_expected = match expected {
+                Some(TypeInfo::Array(t, _)) => Some(&**t),
+                _ => None,
+            };
             if items.is_empty() {
+                let item_t = match item_expected {
+                    Some(t) => t.clone(),
+                    None => ctx.fresh_var(),
+                };
                 return Some(TypedTree(
-                    TypeInfo::Array(Box::new(TypeInfo::Unit)),
+                    TypeInfo::Array(Box::new(item_t), Some(0)),
                     TypedOp::Array(Vec::new()),
                 ));
             }
-            let first_opt = into_typed_tree(ctx, &items[0]);
-            let mut out_opt = first_opt.map(|x| (x.0, vec![x.1]));
-            for it in &items[1..] {
-                let it_opt = into_typed_tree(ctx, it);
+            let mut join = Join::new(ctx, item_expected);
+            let mut out_opt = Some(Vec::new());
+            for it in items {
+                let it_opt = check_or_infer(ctx, it, join.hint());
+                if let Some(it) = &it_opt {
+                    join.add(ctx, &it.0);
+                }
                 match (&mut out_opt, it_opt) {
-                    (Some((first_type, out)), Some(it)) => {
-                        if it.0.eq(first_type) {
-                            out.push(it.1);
-                        } else {
-                            writeln!(
-                                &mut ctx.error_log,
-                                "Array elements type mismatch: {:?} vs {first_type:?}",
-                                it.0
-                            )
-                            .unwrap();
-                            out_opt = None;
-                        }
-                    }
+                    (Some(out), Some(it)) => out.push(it.1),
                     _ => out_opt = None,
                 }
             }
-            let (out_type, out_items) = out_opt?;
-            let array_t = TypeInfo::Array(Box::new(out_type));
+            let out_items = out_opt?;
+            let out_type = join.finish(ctx)?;
+            let array_t = TypeInfo::Array(Box::new(out_type), Some(out_items.len()));
             Some(TypedTree(array_t, TypedOp::Array(out_items)))
         }
-        SyntaxTree::LiteralArrayType(inner) => {
-            let inner1 = into_typed_tree(ctx, inner)?;
-            let inner2 = match_ok!(&mut ctx.error_log, &inner1.0, TypeInfo::Type(t) => t)?;
-            let array_tt = TypeInfo::Type(Box::new(TypeInfo::Array(inner2.clone())));
-            Some(TypedTree(array_tt, TypedOp::ArrayT(Box::new(inner1))))
+        SyntaxTree::LiteralArrayType(inner, len) => {
+            let (inner_t, inner) = into_type_expr(ctx, inner)?;
+            let array_tt = TypeInfo::Type(Box::new(TypeInfo::Array(Box::new(inner_t), *len)));
+            Some(TypedTree(array_tt, TypedOp::ArrayT(Box::new(inner), *len)))
         }
         SyntaxTree::Arithmetic(op, operands) => {
-            guard!(&mut ctx.error_log, !operands.is_empty());
-            let mut out_opt = Some(Vec::new());
+            guard!(
+                &mut ctx.error_log,
+                !operands.is_empty(),
+                format!("`{tree}` expects at least one operand")
+            );
+            // Scalars broadcast over arrays, arrays combine only with the same nesting
+            let mut out_opt = Some((TypeInfo::Int64, Vec::new()));
             for operand in operands {
-                let rhs_opt = into_typed_tree(ctx, operand);
-                let rhs_val_opt = match_ok!(&mut ctx.error_log, rhs_opt, Some(TypedTree(TypeInfo::Int64, x)) => x);
-                match (&mut out_opt, rhs_val_opt) {
-                    (Some(out), Some(item)) => out.push(TypedTree(TypeInfo::Int64, item)),
+                let item_opt = into_typed_tree(ctx, operand);
+                let item_opt = item_opt.and_then(|item| {
+                    let Some(item_t) = numeric_type(ctx, &item.0) else {
+                        let found = ctx.resolve(&item.0);
+                        writeln!(
+                            &mut ctx.error_log,
+                            "Type mismatch in `{operand}`: expected i64 or an array of i64, found {found}"
+                        )
+                        .unwrap();
+                        return None;
+                    };
+                    Some((item_t, item))
+                });
+                match (&mut out_opt, item_opt) {
+                    (Some((out_t, out)), Some((item_t, item))) => {
+                        if *out_t == TypeInfo::Int64 {
+                            *out_t = item_t;
+                        } else if item_t != TypeInfo::Int64 {
+                            let Some(merged) = merge_shapes(ctx, out_t, &item_t) else {
+                                writeln!(
+                                    &mut ctx.error_log,
+                                    "Mismatched array shapes in `{tree}`: {out_t} and {item_t}"
+                                )
+                                .unwrap();
+                                out_opt = None;
+                                continue;
+                            };
+                            *out_t = merged;
+                        }
+                        out.push(item);
+                    }
                     _ => out_opt = None,
                 }
             }
+            let (out_t, out) = out_opt?;
+            Some(TypedTree(out_t, TypedOp::Arithmetic(*op, out)))
+        }
+        SyntaxTree::ArrayGet(array_s, index) => {
+            let array_opt = into_typed_tree(ctx, array_s);
+            let index_t = check_typed_tree(ctx, index, &TypeInfo::Int64)?;
+            let array = array_opt?;
+            let inner = ctx.fresh_var();
+            let array_t = TypeInfo::Array(Box::new(inner.clone()), None);
+            unify_at(ctx, array_s, &array_t, &array.0)?;
+            check_index(ctx, array_s, &array.0, index)?;
             Some(TypedTree(
-                TypeInfo::Int64,
-                TypedOp::Arithmetic(*op, out_opt?),
+                inner,
+                TypedOp::ArrayGet(Box::new(array.1), Box::new(index_t.1)),
             ))
         }
-        SyntaxTree::ArrayGet(array, index) => {
-            let array_opt = into_typed_tree(ctx, array);
-            let index = into_typed_tree(ctx, index)?;
-            let array = array_opt?;
-            let inner_opt = match_ok!(&mut ctx.error_log, array.0, TypeInfo::Array(t) => t);
-            guard!(&mut ctx.error_log, matches!(index.0, TypeInfo::Int64));
+        SyntaxTree::ArrayOp(op, place_s, args) => {
+            let inner = ctx.fresh_var();
+            let place_opt = into_typed_place(ctx, place_s);
+            let place_opt = place_opt.and_then(|(place_t, place)| {
+                let array_t = TypeInfo::Array(Box::new(inner.clone()), None);
+                unify_at(ctx, place_s, &array_t, &place_t)?;
+                Some((place_t, place))
+            });
+            let val_opt = match args.first() {
+                Some(val) => check_typed_tree(ctx, val, &inner).map(Some),
+                None => Some(None),
+            };
+            let mutable_opt = check_mutable(ctx, tree, place_s.root());
+            let ((place_t, place), val) = (place_opt?, val_opt?);
+            mutable_opt?;
+            let array_t = ctx.resolve(&place_t);
+            if let TypeInfo::Array(_, Some(_)) = array_t {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Cannot change the length of `{place_s}` of fixed-length type {array_t}"
+                )
+                .unwrap();
+                return None;
+            }
+            let out_t = match op {
+                ArrayOp::Push => TypeInfo::Unit,
+                ArrayOp::Pop => inner,
+            };
+            let out = val.into_iter().collect();
+            Some(TypedTree(out_t, TypedOp::ArrayOp(*op, place, out)))
+        }
+        SyntaxTree::Form(form, args) => {
+            let name = form.0.name();
+            // Local bindings shadow forms of the same name
+            if ctx.variables.contains_key(name) {
+                let func_opt = typed_local(ctx, name);
+                return into_typed_call(ctx, &name, func_opt, args);
+            }
+            let (arity, found) = (form.0.arity(), args.len());
+            if arity != found {
+                writeln!(
+                    &mut ctx.error_log,
+                    "`{name}` expects {arity} operands, got {found}"
+                )
+                .unwrap();
+                return None;
+            }
+            let (arg_ts, out_t) = form.0.signature(ctx);
+            // Functions go last so that their parameter types are known from the other operands
+            let mut order: Vec<_> = (0..args.len()).collect();
+            order.sort_by_key(|&i| matches!(arg_ts[i], TypeInfo::Func(_, _)));
+            let mut out_opt = Some(vec![None; args.len()]);
+            for i in order {
+                let arg_opt = check_typed_tree(ctx, &args[i], &arg_ts[i]);
+                match (&mut out_opt, arg_opt) {
+                    (Some(out), Some(arg)) => out[i] = Some(arg),
+                    _ => out_opt = None,
+                }
+            }
+            let out: Vec<_> = out_opt?.into_iter().flatten().collect();
+            let found: Vec<_> = out.iter().map(|x| ctx.resolve(&x.0)).collect();
+            if let Err(err) = form.0.validate(&found) {
+                writeln!(&mut ctx.error_log, "{err} in `{tree}`").unwrap();
+                return None;
+            }
+            Some(TypedTree(out_t, TypedOp::Form(form.clone(), out)))
+        }
+        SyntaxTree::LiteralStructType(fields) => {
+            let mut out_opt = Some((Vec::new(), Vec::new()));
+            for (name, field) in fields {
+                let field_opt = into_type_expr(ctx, field);
+                match (&mut out_opt, field_opt) {
+                    (Some((types, out)), Some((t, field))) => {
+                        types.push((name.clone(), t));
+                        out.push(field);
+                    }
+                    _ => out_opt = None,
+                }
+            }
+            let (types, out) = out_opt?;
+            let id = ctx.type_count;
+            ctx.type_count += 1;
+            let struct_t = TypeInfo::Struct(Rc::new(StructType {
+                id,
+                params: Vec::new(),
+                fields: types,
+            }));
             Some(TypedTree(
-                *inner_opt?,
-                TypedOp::ArrayGet(Box::new(array.1), Box::new(index.1)),
+                TypeInfo::Type(Box::new(struct_t)),
+                TypedOp::StructT(out),
             ))
         }
-        SyntaxTree::ArraySet(array, index, val) => {
-            let array_opt = into_typed_tree(ctx, array);
-            let index_opt = into_typed_tree(ctx, index);
-            let val = into_typed_tree(ctx, val)?;
-            let array = array_opt?;
-            let index = index_opt?;
-            let inner = match_ok!(&mut ctx.error_log, array.0, TypeInfo::Array(t) => t)?;
-            guard!(&mut ctx.error_log, matches!(index.0, TypeInfo::Int64));
-            guard!(&mut ctx.error_log, val.0.eq(&inner));
+        SyntaxTree::LiteralStruct(struct_t, fields) => {
+            let struct_t = into_type_expr(ctx, struct_t)?.0;
+            let struct_t = ctx.resolve(&struct_t);
+            let struct_t = match_ok!(
+                &mut ctx.error_log,
+                struct_t,
+                t @ TypeInfo::Struct(_) => t,
+                format!("Expected a struct type in `{tree}`, found {struct_t}")
+            )?;
+            let TypeInfo::Struct(info) = &struct_t else {
+                unreachable!()
+            };
+            let mut out: Vec<Option<TypedTree>> = vec![None; info.fields.len()];
+            let mut ok = true;
+            for (name, val) in fields {
+                let Some(index) = info.field_index(name) else {
+                    writeln!(&mut ctx.error_log, "Unknown field {name:?}").unwrap();
+                    into_typed_tree(ctx, val);
+                    ok = false;
+                    continue;
+                };
+                out[index] = check_typed_tree(ctx, val, &info.fields[index].1);
+                ok &= out[index].is_some();
+            }
+            for ((name, _), val) in info.fields.iter().zip(&out) {
+                if val.is_none() && !fields.iter().any(|(x, _)| x == name) {
+                    writeln!(&mut ctx.error_log, "Missing field {name:?}").unwrap();
+                    ok = false;
+                }
+            }
+            guard_opt!(ok);
+            let out = out.into_iter().collect::<Option<Vec<_>>>()?;
+            Some(TypedTree(struct_t, TypedOp::Struct(out)))
+        }
+        SyntaxTree::FieldGet(record, field) => {
+            let record = into_typed_tree(ctx, record)?;
+            let record_t = ctx.resolve(&record.0);
+            let info = match_ok!(
+                &mut ctx.error_log,
+                &record_t,
+                TypeInfo::Struct(x) => x,
+                format!("Expected a struct in `{tree}`, found {record_t}")
+            )?;
+            let index = match_ok!(
+                &mut ctx.error_log,
+                info.field_index(field),
+                Some(x) => x,
+                format!("Unknown field {field:?} in `{tree}` of type {record_t}")
+            )?;
             Some(TypedTree(
-                *inner,
-                TypedOp::ArraySet(Box::new(array.1), Box::new(index.1), Box::new(val)),
+                info.fields[index].1.clone(),
+                TypedOp::FieldGet(Box::new(record.1), index),
             ))
         }
-    }
-}
-
-pub fn interpret<'a>(ctx: &mut RuntimeContext<'a>, tree: &'a TokenTree) -> Result<Value, String> {
-    match tree {
-        TokenTree::Atom(var) => ctx
-            .variables
-            .get(&var[..])
-            .cloned()
-            .ok_or_else(|| format!("Unknown variable {var:?}")),
-        TokenTree::Array(arr) => {
-            guard!(!arr.is_empty());
-            match &arr[0] {
-                TokenTree::Atom(s) => match &s[..] {
-                    "+" => {
-                        let mut acc = match_ok!(interpret(ctx, &arr[1])?, Value::Int64(x) => x)?;
-                        for x in &arr[2..] {
-                            let y = match_ok!(interpret(ctx, x)?, Value::Int64(y) => y)?;
-                            acc = acc.wrapping_add(y);
-                        }
-                        Ok(Value::Int64(acc))
-                    }
-                    "-" => {
-                        let mut acc = match_ok!(interpret(ctx, &arr[1])?, Value::Int64(x) => x)?;
-                        for x in &arr[2..] {
-                            let y = match_ok!(interpret(ctx, x)?, Value::Int64(y) => y)?;
-                            acc = acc.wrapping_sub(y);
-                        }
-                        Ok(Value::Int64(acc))
-                    }
-                    "*" => {
-                        let mut acc = match_ok!(interpret(ctx, &arr[1])?, Value::Int64(y) => y)?;
-                        for x in &arr[2..] {
-                            let y = match_ok!(interpret(ctx, x)?, Value::Int64(y) => y)?;
-                            acc = acc.wrapping_mul(y);
+        SyntaxTree::LiteralEnumType(variants) => {
+            let mut out_opt = Some((Vec::new(), Vec::new()));
+            for (name, payload) in variants {
+                let mut payload_opt = Some((Vec::new(), Vec::new()));
+                for it in payload {
+                    let it_opt = into_type_expr(ctx, it);
+                    match (&mut payload_opt, it_opt) {
+                        (Some((types, out)), Some((t, it))) => {
+                            types.push(t);
+                            out.push(it);
                         }
-                        Ok(Value::Int64(acc))
+                        _ => payload_opt = None,
                     }
-                    "/" => {
-                        let mut acc = match_ok!(interpret(ctx, &arr[1])?, Value::Int64(y) => y)?;
-                        for x in &arr[2..] {
-                            let y = match_ok!(interpret(ctx, x)?, Value::Int64(y) => y)?;
-                            guard!(y != 0);
-                            acc /= y;
-                        }
-                        Ok(Value::Int64(acc))
+                }
+                match (&mut out_opt, payload_opt) {
+                    (Some((types, out)), Some((payload_types, payload))) => {
+                        types.push((name.clone(), payload_types));
+                        out.push(payload);
                     }
-                    "let" => {
-                        guard!(arr.len() == 4);
-                        let var = match_ok!(&arr[1], TokenTree::Atom(x) => x)?;
-                        let val = interpret(ctx, &arr[2])?;
-                        let old_val = ctx.variables.insert(var, val);
-                        let body = interpret(ctx, &arr[3]);
-                        insert_or_remove(&mut ctx.variables, var, old_val);
-                        body
+                    _ => out_opt = None,
+                }
+            }
+            let (types, out) = out_opt?;
+            let id = ctx.type_count;
+            ctx.type_count += 1;
+            let enum_t = TypeInfo::Enum(Rc::new(EnumType {
+                id,
+                params: Vec::new(),
+                variants: types,
+            }));
+            Some(TypedTree(
+                TypeInfo::Type(Box::new(enum_t)),
+                TypedOp::EnumT(out),
+            ))
+        }
+        SyntaxTree::LiteralVariant(enum_t, name, payload) => {
+            let enum_t = into_type_expr(ctx, enum_t)?.0;
+            let enum_t = ctx.resolve(&enum_t);
+            let enum_t = match_ok!(
+                &mut ctx.error_log,
+                enum_t,
+                t @ TypeInfo::Enum(_) => t,
+                format!("Expected an enum type in `{tree}`, found {enum_t}")
+            )?;
+            let TypeInfo::Enum(info) = &enum_t else {
+                unreachable!()
+            };
+            let Some(index) = info.variant_index(name) else {
+                writeln!(&mut ctx.error_log, "Unknown variant {name:?}").unwrap();
+                return None;
+            };
+            let payload_t = &info.variants[index].1;
+            if payload.len() != payload_t.len() {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Variant {name:?} expects {} fields, got {}",
+                    payload_t.len(),
+                    payload.len()
+                )
+                .unwrap();
+                return None;
+            }
+            let mut payload_opt = Some(Vec::new());
+            for (it, it_t) in payload.iter().zip(payload_t) {
+                let it_opt = check_typed_tree(ctx, it, it_t);
+                match (&mut payload_opt, it_opt) {
+                    (Some(out), Some(it)) => out.push(it),
+                    _ => payload_opt = None,
+                }
+            }
+            let payload = payload_opt?;
+            Some(TypedTree(enum_t, TypedOp::Variant(index, payload)))
+        }
+        SyntaxTree::Match(val, arms) => {
+            let val_opt = into_typed_tree(ctx, val);
+            let val_type = val_opt.as_ref().map(|x| ctx.resolve(&x.0));
+            let mut join = Join::new(ctx, expected);
+            let mut out_opt = Some(Vec::new());
+            for (pattern, body) in arms {
+                let mut bindings = Vec::new();
+                let pattern_opt =
+                    into_typed_pattern(ctx, pattern, val_type.as_ref(), &mut bindings);
+                let mut old_types = Vec::new();
+                for (name, typ) in bindings {
+                    if old_types.iter().any(|(x, _)| *x == name) {
+                        writeln!(&mut ctx.error_log, "Duplicate binding {name:?} in pattern")
+                            .unwrap();
+                        out_opt = None;
                     }
-                    "var" => {
-                        guard!(arr.len() == 4);
-                        let var = match_ok!(&arr[1], TokenTree::Atom(x) => x)?;
-                        let typ_val = interpret(ctx, &arr[2])?;
-                        let typ = match_ok!(&typ_val, Value::Type(x) => x)?;
-                        let old_val = ctx.variables.insert(var, typ.zero());
-                        let body = interpret(ctx, &arr[3]);
-                        insert_or_remove(&mut ctx.variables, var, old_val);
-                        body
+                    old_types.push((name, bind(ctx, name, typ, Declaration::Let(tree))));
+                }
+                let body_opt = check_or_infer(ctx, body, join.hint());
+                for (name, old) in old_types.into_iter().rev() {
+                    unbind(ctx, name, old);
+                }
+                if let Some(body) = &body_opt {
+                    join.add(ctx, &body.0);
+                }
+                match (&mut out_opt, pattern_opt, body_opt) {
+                    (Some(out), Some(pattern), Some(body)) => out.push((pattern, body)),
+                    _ => out_opt = None,
+                }
+            }
+            let val = val_opt?;
+            let out = out_opt?;
+            let out_type = join.finish(ctx)?;
+            let mut ok = true;
+            let patterns: Vec<_> = out.iter().map(|x| &x.0).collect();
+            let val_type = ctx.resolve(&val.0);
+            for index in redundant_arms(&patterns, &val_type) {
+                let pattern = &arms[index].0;
+                writeln!(&mut ctx.error_log, "Unreachable match arm {pattern}").unwrap();
+                ok = false;
+            }
+            for missing in missing_patterns(&patterns, &val_type) {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Non-exhaustive match: {missing} not covered"
+                )
+                .unwrap();
+                ok = false;
+            }
+            guard_opt!(ok);
+            Some(TypedTree(out_type, TypedOp::Match(Box::new(val), out)))
+        }
+        SyntaxTree::LetPattern(pattern, val, body) => {
+            let val_opt = into_typed_tree(ctx, val);
+            let val_type = val_opt.as_ref().map(|x| ctx.resolve(&x.0));
+            let mut bindings = Vec::new();
+            let pattern_opt = into_typed_pattern(ctx, pattern, val_type.as_ref(), &mut bindings);
+            let mut old_types = Vec::new();
+            let mut ok = true;
+            for (name, typ) in bindings {
+                if old_types.iter().any(|(x, _)| *x == name) {
+                    writeln!(&mut ctx.error_log, "Duplicate binding {name:?} in pattern").unwrap();
+                    ok = false;
+                }
+                old_types.push((name, bind(ctx, name, typ, Declaration::Let(tree))));
+            }
+            let body_opt = into_typed_tree_expected(ctx, body, expected);
+            for (name, old) in old_types.into_iter().rev() {
+                unbind(ctx, name, old);
+            }
+            guard_opt!(ok);
+            let body = body_opt?;
+            Some(TypedTree(
+                body.0.clone(),
+                TypedOp::Destructure(pattern_opt?, Box::new(val_opt?), Box::new(body)),
+            ))
+        }
+        SyntaxTree::LiteralTupleType(items) => {
+            let mut out_opt = Some((Vec::new(), Vec::new()));
+            for it in items {
+                let it_opt = into_type_expr(ctx, it);
+                match (&mut out_opt, it_opt) {
+                    (Some((types, out)), Some((t, it))) => {
+                        types.push(t);
+                        out.push(it);
+                    }
+                    _ => out_opt = None,
+                }
+            }
+            let (types, out) = out_opt?;
+            Some(TypedTree(
+                TypeInfo::Type(Box::new(TypeInfo::Tuple(types))),
+                TypedOp::TupleT(out),
+            ))
+        }
+        SyntaxTree::LiteralTuple(items) => {
+            let items_expected = match expected {
+                Some(TypeInfo::Tuple(x)) if x.len() == items.len() => Some(x),
+                _ => None,
+            };
+            let mut out_opt = Some(Vec::new());
+            for (i, it) in items.iter().enumerate() {
+                let it_opt = check_or_infer(ctx, it, items_expected.map(|x| &x[i]));
+                match (&mut out_opt, it_opt) {
+                    (Some(out), Some(it)) => out.push(it),
+                    _ => out_opt = None,
+                }
+            }
+            let out = out_opt?;
+            let tuple_t = TypeInfo::Tuple(out.iter().map(|x| x.0.clone()).collect());
+            Some(TypedTree(tuple_t, TypedOp::Tuple(out)))
+        }
+        SyntaxTree::TupleGet(tuple, index) => {
+            let tuple = into_typed_tree(ctx, tuple)?;
+            let tuple_t = ctx.resolve(&tuple.0);
+            let items = match_ok!(
+                &mut ctx.error_log,
+                tuple_t,
+                TypeInfo::Tuple(x) => x,
+                format!("Expected a tuple in `{tree}`, found {tuple_t}")
+            )?;
+            let Some(item_t) = items.get(*index) else {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Tuple index {index} out of bounds for {} elements",
+                    items.len()
+                )
+                .unwrap();
+                return None;
+            };
+            Some(TypedTree(
+                item_t.clone(),
+                TypedOp::TupleGet(Box::new(tuple.1), *index),
+            ))
+        }
+        SyntaxTree::LiteralStr(x) => Some(TypedTree(
+            TypeInfo::Str,
+            TypedOp::Const(Value::Str(x.clone())),
+        )),
+        SyntaxTree::Quote(x) => Some(TypedTree(TypeInfo::Sexp, TypedOp::Const(quote_value(x)))),
+        SyntaxTree::Quasiquote(x) => {
+            let out = into_typed_quasi(ctx, x)?;
+            Some(TypedTree(TypeInfo::Sexp, TypedOp::Quasiquote(out)))
+        }
+        SyntaxTree::Raise(val) => {
+            let val = into_typed_tree(ctx, val)?;
+            // Evaluation never continues past `raise`, so it fits any context
+            let out_t = match expected {
+                Some(t) => t.clone(),
+                None => ctx.fresh_var(),
+            };
+            Some(TypedTree(out_t, TypedOp::Raise(Box::new(val))))
+        }
+        SyntaxTree::Propagate(val_s) => {
+            let val = into_typed_tree(ctx, val_s)?;
+            let Some(ret) = ctx.return_types.last().cloned() else {
+                writeln!(&mut ctx.error_log, "`?` outside of a function in `{tree}`").unwrap();
+                return None;
+            };
+            let p = Propagate {
+                tree,
+                val_s,
+                val_t: val.0.clone(),
+                out_t: ctx.fresh_var(),
+                ret,
+            };
+            let out_t = p.out_t.clone();
+            // An unannotated parameter may only get its type further on in the function
+            if let TypeInfo::Var(_) = propagate_kind(ctx, &p) {
+                ctx.propagates.push(p);
+            } else {
+                check_propagate(ctx, &p)?;
+            }
+            Some(TypedTree(out_t, TypedOp::Propagate(Box::new(val))))
+        }
+        SyntaxTree::Try(body, catches, finally) => {
+            let mut join = Join::new(ctx, expected);
+            let body_opt = check_or_infer(ctx, body, join.hint());
+            if let Some(body) = &body_opt {
+                join.add(ctx, &body.0);
+            }
+            let mut out_opt = Some(Vec::new());
+            for it in catches {
+                let catch_opt = match it {
+                    Catch::Any(handler) => {
+                        check_or_infer(ctx, handler, join.hint()).map(TypedCatch::Any)
+                    }
+                    Catch::Typed(var, typ, handler) => {
+                        let typ_opt = into_type_expr(ctx, typ);
+                        let var_type = typ_opt.as_ref().map(|x| x.0.clone());
+                        let old = bind(ctx, var, var_type, Declaration::Let(tree));
+                        let handler_opt = check_or_infer(ctx, handler, join.hint());
+                        unbind(ctx, var, old);
+                        typ_opt
+                            .zip(handler_opt)
+                            .map(|((_, typ), handler)| TypedCatch::Typed(var.clone(), typ, handler))
+                    }
+                };
+                if let Some(TypedCatch::Any(x) | TypedCatch::Typed(_, _, x)) = &catch_opt {
+                    join.add(ctx, &x.0);
+                }
+                match (&mut out_opt, catch_opt) {
+                    (Some(out), Some(catch)) => out.push(catch),
+                    _ => out_opt = None,
+                }
+            }
+            let finally_opt = match finally {
+                Some(x) => into_typed_tree(ctx, x).map(|x| Some(Box::new(x))),
+                None => Some(None),
+            };
+            let out = TypedOp::Try(Box::new(body_opt?), out_opt?, finally_opt?);
+            Some(TypedTree(join.finish(ctx)?, out))
+        }
+        SyntaxTree::LiteralMapType(key, val) => {
+            let key_opt = into_type_expr(ctx, key);
+            let (val_t, val) = into_type_expr(ctx, val)?;
+            let (key_t, key) = key_opt?;
+            if !key_t.is_hashable() {
+                writeln!(&mut ctx.error_log, "Type {key_t} cannot be a map key").unwrap();
+                return None;
+            }
+            let map_tt = TypeInfo::Type(Box::new(TypeInfo::Map(Box::new(key_t), Box::new(val_t))));
+            Some(TypedTree(
+                map_tt,
+                TypedOp::MapT(Box::new(key), Box::new(val)),
+            ))
+        }
+        SyntaxTree::LiteralMap(items) => {
+            let (key_t, val_t) = match expected {
+                Some(TypeInfo::Map(k, v)) => (Some(&**k), Some(&**v)),
+                _ => (None, None),
+            };
+            if items.is_empty() {
+                let key_t = key_t.cloned().unwrap_or_else(|| ctx.fresh_var());
+                let val_t = val_t.cloned().unwrap_or_else(|| ctx.fresh_var());
+                return Some(TypedTree(
+                    TypeInfo::Map(Box::new(key_t), Box::new(val_t)),
+                    TypedOp::Map(Vec::new()),
+                ));
+            }
+            let (mut key_join, mut val_join) = (Join::new(ctx, key_t), Join::new(ctx, val_t));
+            let mut out_opt = Some(Vec::new());
+            for (key, val) in items {
+                let key_opt = check_or_infer(ctx, key, key_join.hint());
+                let val_opt = check_or_infer(ctx, val, val_join.hint());
+                if let Some(key) = &key_opt {
+                    key_join.add(ctx, &key.0);
+                }
+                if let Some(val) = &val_opt {
+                    val_join.add(ctx, &val.0);
+                }
+                match (&mut out_opt, key_opt, val_opt) {
+                    (Some(out), Some(key), Some(val)) => out.push((key, val)),
+                    _ => out_opt = None,
+                }
+            }
+            let out = out_opt?;
+            let (key_t, val_t) = (key_join.finish(ctx)?, val_join.finish(ctx)?);
+            if !key_t.is_hashable() {
+                writeln!(&mut ctx.error_log, "Type {key_t} cannot be a map key").unwrap();
+                return None;
+            }
+            let map_t = TypeInfo::Map(Box::new(key_t), Box::new(val_t));
+            Some(TypedTree(map_t, TypedOp::Map(out)))
+        }
+        SyntaxTree::MapOp(op, args) => {
+            let map_opt = into_typed_tree(ctx, &args[0]);
+            let map_t_opt = map_opt.as_ref().and_then(|map| {
+                let (key_t, val_t) = (ctx.fresh_var(), ctx.fresh_var());
+                let map_t = TypeInfo::Map(Box::new(key_t.clone()), Box::new(val_t.clone()));
+                unify_at(ctx, &args[0], &map_t, &map.0)?;
+                Some((key_t, val_t))
+            });
+            let mut out_opt = map_opt.map(|x| vec![x]);
+            for (i, arg) in args[1..].iter().enumerate() {
+                let arg_t = map_t_opt.as_ref().map(|(k, v)| if i == 0 { k } else { v });
+                let arg_opt = check_or_infer(ctx, arg, arg_t);
+                match (&mut out_opt, arg_opt) {
+                    (Some(out), Some(arg)) => out.push(arg),
+                    _ => out_opt = None,
+                }
+            }
+            let out = out_opt?;
+            let (key_t, val_t) = map_t_opt?;
+            let out_t = match op {
+                MapOp::Get => val_t,
+                MapOp::Contains | MapOp::Len => TypeInfo::Int64,
+                MapOp::Keys => TypeInfo::Array(Box::new(key_t), None),
+            };
+            Some(TypedTree(out_t, TypedOp::MapOp(*op, out)))
+        }
+        SyntaxTree::MapUpdate(op, place_s, args) => {
+            let place_opt = into_typed_place(ctx, place_s);
+            let map_t_opt = place_opt.as_ref().and_then(|(place_t, _)| {
+                let (key_t, val_t) = (ctx.fresh_var(), ctx.fresh_var());
+                let map_t = TypeInfo::Map(Box::new(key_t.clone()), Box::new(val_t.clone()));
+                unify_at(ctx, place_s, &map_t, place_t)?;
+                Some((key_t, val_t))
+            });
+            let mut out_opt = Some(Vec::new());
+            for (i, arg) in args.iter().enumerate() {
+                let arg_t = map_t_opt.as_ref().map(|(k, v)| if i == 0 { k } else { v });
+                let arg_opt = check_or_infer(ctx, arg, arg_t);
+                match (&mut out_opt, arg_opt) {
+                    (Some(out), Some(arg)) => out.push(arg),
+                    _ => out_opt = None,
+                }
+            }
+            let mutable_opt = check_mutable(ctx, tree, place_s.root());
+            let out = out_opt?;
+            let (_, place) = place_opt?;
+            map_t_opt?;
+            mutable_opt?;
+            Some(TypedTree(
+                TypeInfo::Unit,
+                TypedOp::MapUpdate(*op, place, out),
+            ))
+        }
+        SyntaxTree::Ascribe(val, typ) => {
+            let typ_opt = into_type_expr(ctx, typ).map(|x| x.0);
+            let val_opt = check_or_infer(ctx, val, typ_opt.as_ref());
+            typ_opt?;
+            val_opt
+        }
+        SyntaxTree::LiteralFuncType(params, ret) => {
+            let mut out_opt = Some((Vec::new(), Vec::new()));
+            for it in params.iter().chain([&**ret]) {
+                let it_opt = into_type_expr(ctx, it);
+                match (&mut out_opt, it_opt) {
+                    (Some((types, out)), Some((t, it))) => {
+                        types.push(t);
+                        out.push(it);
+                    }
+                    _ => out_opt = None,
+                }
+            }
+            let (mut types, mut out) = out_opt?;
+            let (ret_t, ret) = (types.pop()?, out.pop()?);
+            let func_t = TypeInfo::Func(types, Box::new(ret_t));
+            Some(TypedTree(
+                TypeInfo::Type(Box::new(func_t)),
+                TypedOp::FuncT(out, Box::new(ret)),
+            ))
+        }
+        SyntaxTree::Lambda(params, body_s) => {
+            let (params_expected, ret_expected) = match expected {
+                Some(TypeInfo::Func(x, ret)) if x.len() == params.len() => (Some(x), Some(&**ret)),
+                _ => (None, None),
+            };
+            let mut ok = true;
+            let mut param_types = Vec::new();
+            for (i, (_, typ)) in params.iter().enumerate() {
+                let param_t = match (typ, params_expected) {
+                    (Some(typ), _) => into_type_expr(ctx, typ).map(|x| x.0),
+                    (None, Some(x)) => Some(x[i].clone()),
+                    (None, None) => Some(ctx.fresh_var()),
+                };
+                ok &= param_t.is_some();
+                param_types.push(param_t);
+            }
+            let mut old_types = Vec::new();
+            for ((name, _), typ) in params.iter().zip(&param_types) {
+                old_types.push((
+                    &name[..],
+                    bind(ctx, name, typ.clone(), Declaration::Let(tree)),
+                ));
+            }
+            let ret = match ret_expected {
+                Some(t) => t.clone(),
+                None => ctx.fresh_var(),
+            };
+            ctx.return_types.push(ret.clone());
+            let propagates_len = ctx.propagates.len();
+            let body_opt = check_or_infer(ctx, body_s, ret_expected);
+            ctx.return_types.pop();
+            let propagates = ctx.propagates.split_off(propagates_len);
+            for (name, old) in old_types.into_iter().rev() {
+                unbind(ctx, name, old);
+            }
+            guard_opt!(ok);
+            let body = body_opt?;
+            // Early returns through `?` have constrained `ret` already
+            unify_at(ctx, &**body_s, &ret, &body.0)?;
+            let mut ok = true;
+            for p in &propagates {
+                ok &= check_propagate(ctx, p).is_some();
+            }
+            guard_opt!(ok);
+            let param_types = param_types.into_iter().collect::<Option<Vec<_>>>()?;
+            let names = params.iter().map(|x| x.0.clone()).collect();
+            Some(TypedTree(
+                TypeInfo::Func(param_types, Box::new(body.0.clone())),
+                TypedOp::Lambda(names, Box::new(body)),
+            ))
+        }
+        SyntaxTree::Generic(params, body) => {
+            with_type_params(ctx, tree, params, |ctx| into_typed_tree(ctx, body))
+        }
+        SyntaxTree::LetRec(var, val, body) => {
+            let self_t = ctx.fresh_var();
+            let old = bind(ctx, var, Some(self_t.clone()), Declaration::Let(tree));
+            let val_opt = match &**val {
+                SyntaxTree::Generic(params, lambda) => with_type_params(ctx, val, params, |ctx| {
+                    check_typed_tree(ctx, lambda, &self_t)
+                }),
+                _ => check_typed_tree(ctx, val, &self_t),
+            };
+            // The function is monomorphic inside its own body and generalized only after it
+            ctx.variables.remove(&var[..]);
+            let var_type_opt = val_opt.as_ref().map(|x| ctx.generalize(&x.0));
+            ctx.variables.insert(var, var_type_opt);
+            let body_opt = into_typed_tree_expected(ctx, body, expected);
+            unbind(ctx, var, old);
+            let body = body_opt?;
+            Some(TypedTree(
+                body.0.clone(),
+                TypedOp::LetRec(var.clone(), Box::new(val_opt?), Box::new(body)),
+            ))
+        }
+        SyntaxTree::Call(func_s, args) => {
+            let func_opt = into_typed_tree(ctx, func_s);
+            into_typed_call(ctx, func_s, func_opt, args)
+        }
+    }
+}
+
+// A variable, or a form used as a function value when no variable of its name is in scope
+fn typed_local(ctx: &mut TypeContext<'_>, var: &str) -> Option<TypedTree> {
+    match ctx.variables.get(var) {
+        None => match forms::lookup(var) {
+            Some(form) => {
+                let (params, ret) = form.0.signature(ctx);
+                let func_t = TypeInfo::Func(params, Box::new(ret));
+                Some(TypedTree(func_t, TypedOp::LocalGet(0, var.into())))
+            }
+            None => {
+                writeln!(&mut ctx.error_log, "Unknown variable {var}").unwrap();
+                None
+            }
+        },
+        Some(None) => {
+            // Errors about variable types do not make noise as errors about missing variables
+            None
+        }
+        Some(Some(found)) => {
+            let found = found.clone();
+            let found = ctx.instantiate(&found);
+            Some(TypedTree(found, TypedOp::LocalGet(0, var.into())))
+        }
+    }
+}
+
+fn into_typed_call<'a>(
+    ctx: &mut TypeContext<'a>,
+    func_s: &impl Display,
+    func_opt: Option<TypedTree>,
+    args: &'a [SyntaxTree],
+) -> Option<TypedTree> {
+    let func_t = func_opt.as_ref().map(|x| {
+        let t = ctx.resolve(&x.0);
+        ctx.instantiate(&t)
+    });
+    let sig_opt = match func_t {
+        Some(TypeInfo::Type(t)) => {
+            let out = apply_type(ctx, func_s, &t, args)?;
+            let out_tt = TypeInfo::Type(Box::new(out.clone()));
+            return Some(TypedTree(out_tt, TypedOp::Const(Value::Type(out))));
+        }
+        Some(TypeInfo::Func(params, ret)) if params.len() == args.len() => Some((params, *ret)),
+        Some(TypeInfo::Func(params, _)) => {
+            writeln!(
+                &mut ctx.error_log,
+                "Function `{func_s}` expects {} arguments, got {}",
+                params.len(),
+                args.len()
+            )
+            .unwrap();
+            None
+        }
+        Some(t @ TypeInfo::Var(_)) => {
+            let params: Vec<_> = args.iter().map(|_| ctx.fresh_var()).collect();
+            let ret = ctx.fresh_var();
+            let func_t = TypeInfo::Func(params.clone(), Box::new(ret.clone()));
+            unify_at(ctx, func_s, &func_t, &t).map(|_| (params, ret))
+        }
+        Some(t) => {
+            writeln!(
+                &mut ctx.error_log,
+                "`{func_s}` is not a function, found {t}"
+            )
+            .unwrap();
+            None
+        }
+        None => None,
+    };
+    let mut out_opt = Some(Vec::new());
+    for (i, arg) in args.iter().enumerate() {
+        let arg_t = sig_opt.as_ref().map(|x| &x.0[i]);
+        let arg_opt = check_or_infer(ctx, arg, arg_t);
+        match (&mut out_opt, arg_opt) {
+            (Some(out), Some(arg)) => out.push(arg),
+            _ => out_opt = None,
+        }
+    }
+    let (func, out) = (func_opt?, out_opt?);
+    let (_, ret) = sig_opt?;
+    Some(TypedTree(ret, TypedOp::Call(Box::new(func), out)))
+}
+
+type Shadowed<'a> = (Option<Option<TypeInfo>>, Option<Declaration<'a>>);
+
+fn into_typed_quasi<'a>(ctx: &mut TypeContext<'a>, quasi: &'a Quasi) -> Option<TypedQuasi> {
+    match quasi {
+        Quasi::Datum(x) => Some(TypedQuasi::Datum(quote_value(x))),
+        Quasi::List(items) => {
+            let mut out_opt = Some(Vec::new());
+            for it in items {
+                let it_opt = into_typed_quasi(ctx, it);
+                match (&mut out_opt, it_opt) {
+                    (Some(out), Some(it)) => out.push(it),
+                    _ => out_opt = None,
+                }
+            }
+            Some(TypedQuasi::List(out_opt?))
+        }
+        // Integers and strings are valid code as they are
+        Quasi::Unquote(x) => {
+            let out = into_typed_tree(ctx, x)?;
+            match ctx.resolve(&out.0) {
+                TypeInfo::Sexp | TypeInfo::Int64 | TypeInfo::Str => {}
+                t @ TypeInfo::Var(_) => _ = ctx.unify(&t, &TypeInfo::Sexp),
+                t => {
+                    writeln!(
+                        &mut ctx.error_log,
+                        "Type mismatch in `{x}`: expected sexp, i64 or str, found {t}"
+                    )
+                    .unwrap();
+                    return None;
+                }
+            }
+            Some(TypedQuasi::Unquote(Box::new(out)))
+        }
+        // Splices either a list or an array of code
+        Quasi::Splice(x) => {
+            let out = into_typed_tree(ctx, x)?;
+            let t = ctx.resolve(&out.0);
+            let ok = match &t {
+                TypeInfo::Sexp => true,
+                TypeInfo::Array(inner, _) => ctx.unify(inner, &TypeInfo::Sexp),
+                TypeInfo::Var(_) => ctx.unify(&t, &TypeInfo::Sexp),
+                _ => false,
+            };
+            if !ok {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Type mismatch in `{x}`: expected sexp or (array-t sexp), found {t}"
+                )
+                .unwrap();
+                return None;
+            }
+            Some(TypedQuasi::Splice(Box::new(out)))
+        }
+    }
+}
+
+// Functions, possibly behind `let`s that rename other values, as imported definitions are
+fn is_syntactic_value(tree: &SyntaxTree) -> bool {
+    match tree {
+        SyntaxTree::Lambda(_, _) | SyntaxTree::Generic(_, _) | SyntaxTree::Ident(_) => true,
+        SyntaxTree::LetVal(_, val, body) => is_syntactic_value(val) && is_syntactic_value(body),
+        _ => false,
+    }
+}
+
+fn bind<'a>(
+    ctx: &mut TypeContext<'a>,
+    name: &'a str,
+    typ: Option<TypeInfo>,
+    decl: Declaration<'a>,
+) -> Shadowed<'a> {
+    (
+        ctx.variables.insert(name, typ),
+        ctx.declarations.insert(name, decl),
+    )
+}
+
+// Restores what `bind` shadowed and returns the type the name had
+fn unbind<'a>(
+    ctx: &mut TypeContext<'a>,
+    name: &'a str,
+    old: Shadowed<'a>,
+) -> Option<Option<TypeInfo>> {
+    insert_or_remove(&mut ctx.declarations, name, old.1);
+    insert_or_remove(&mut ctx.variables, name, old.0)
+}
+
+fn into_typed_place<'a>(
+    ctx: &mut TypeContext<'a>,
+    place: &'a Place,
+) -> Option<(TypeInfo, TypedPlace)> {
+    match place {
+        Place::Var(var) => match ctx.variables.get(&var[..]) {
+            None => {
+                writeln!(&mut ctx.error_log, "Unknown variable {var}").unwrap();
+                None
+            }
+            Some(typ) => Some((typ.clone()?, TypedPlace::Local(0, var.clone()))),
+        },
+        Place::Index(array_s, index) => {
+            let array_opt = into_typed_place(ctx, array_s);
+            let index_opt = check_typed_tree(ctx, index, &TypeInfo::Int64);
+            let (array_t, array) = array_opt?;
+            let inner = ctx.fresh_var();
+            unify_at(
+                ctx,
+                array_s,
+                &TypeInfo::Array(Box::new(inner.clone()), None),
+                &array_t,
+            )?;
+            check_index(ctx, array_s, &array_t, index)?;
+            Some((
+                inner,
+                TypedPlace::Index(Box::new(array), Box::new(index_opt?)),
+            ))
+        }
+        Place::Field(record, field) => {
+            let (record_t, record) = into_typed_place(ctx, record)?;
+            let record_t = ctx.resolve(&record_t);
+            let info = match_ok!(
+                &mut ctx.error_log,
+                &record_t,
+                TypeInfo::Struct(x) => x,
+                format!("Expected a struct in `{place}`, found {record_t}")
+            )?;
+            let index = match_ok!(
+                &mut ctx.error_log,
+                info.field_index(field),
+                Some(x) => x,
+                format!("Unknown field {field:?} in `{place}` of type {record_t}")
+            )?;
+            Some((
+                info.fields[index].1.clone(),
+                TypedPlace::Field(Box::new(record), index),
+            ))
+        }
+    }
+}
+
+// Constant indices into arrays of known length are checked before running
+fn check_index(
+    ctx: &mut TypeContext<'_>,
+    array_s: &impl Display,
+    array_t: &TypeInfo,
+    index: &SyntaxTree,
+) -> Option<()> {
+    let (&SyntaxTree::LiteralInt64(i), TypeInfo::Array(_, Some(len))) =
+        (index, ctx.resolve(array_t))
+    else {
+        return Some(());
+    };
+    if usize::try_from(i).is_ok_and(|i| i < len) {
+        return Some(());
+    }
+    writeln!(
+        &mut ctx.error_log,
+        "Index {i} out of bounds for `{array_s}` of length {len}"
+    )
+    .unwrap();
+    None
+}
+
+// Numbers and arrays of numbers nested to any depth; unknown types are taken to be numbers
+fn numeric_type(ctx: &mut TypeContext<'_>, t: &TypeInfo) -> Option<TypeInfo> {
+    match ctx.resolve(t) {
+        TypeInfo::Int64 => Some(TypeInfo::Int64),
+        TypeInfo::Var(_) => {
+            ctx.unify(t, &TypeInfo::Int64);
+            Some(TypeInfo::Int64)
+        }
+        TypeInfo::Array(inner, len) => {
+            Some(TypeInfo::Array(Box::new(numeric_type(ctx, &inner)?), len))
+        }
+        _ => None,
+    }
+}
+
+// Shape of two arrays combined elementwise: lengths must agree where both are known
+fn merge_shapes(ctx: &mut TypeContext<'_>, x: &TypeInfo, y: &TypeInfo) -> Option<TypeInfo> {
+    match (ctx.resolve(x), ctx.resolve(y)) {
+        (TypeInfo::Array(x, n), TypeInfo::Array(y, m)) => {
+            guard_opt!(n.is_none() || m.is_none() || n == m);
+            let inner = merge_shapes(ctx, &x, &y)?;
+            Some(TypeInfo::Array(Box::new(inner), n.or(m)))
+        }
+        (x, y) => ctx.unify(&x, &y).then_some(x),
+    }
+}
+
+fn check_mutable(ctx: &mut TypeContext<'_>, tree: &SyntaxTree, var: &str) -> Option<()> {
+    if !ctx.variables.contains_key(var) {
+        // Unknown variables are reported by whoever looks up their type
+        return Some(());
+    }
+    let decl = match ctx.declarations.get(var) {
+        Some(&Declaration::Var(depth)) if depth == ctx.return_types.len() => return Some(()),
+        Some(&Declaration::Var(_)) => {
+            writeln!(
+                &mut ctx.error_log,
+                "Cannot assign to `{var}` captured by a function in `{tree}`"
+            )
+            .unwrap();
+            writeln!(
+                &mut ctx.error_log,
+                "note: functions capture a copy of `{var}`, return the new value instead"
+            )
+            .unwrap();
+            return None;
+        }
+        Some(&Declaration::Let(decl)) => Some(decl),
+        None => None,
+    };
+    writeln!(
+        &mut ctx.error_log,
+        "Cannot assign to immutable `{var}` in `{tree}`"
+    )
+    .unwrap();
+    if let Some(decl) = decl {
+        let mut decl_s: String = decl.to_string();
+        if decl_s.chars().count() > 60 {
+            decl_s = decl_s.chars().take(60).chain("...".chars()).collect();
+        }
+        writeln!(
+            &mut ctx.error_log,
+            "note: `{var}` declared immutable here: `{decl_s}`"
+        )
+        .unwrap();
+    }
+    None
+}
+
+// Types are values the checker can compute: a type-valued expression has type `Type(t)`,
+// so once inference pins `t` down it is the result of evaluating the expression
+fn into_type_expr<'a>(
+    ctx: &mut TypeContext<'a>,
+    tree: &'a SyntaxTree,
+) -> Option<(TypeInfo, TypedTree)> {
+    let out = into_typed_tree(ctx, tree)?;
+    let t = match ctx.resolve(&out.0) {
+        TypeInfo::Type(t) => *t,
+        TypeInfo::Var(_) => {
+            let t = ctx.fresh_var();
+            ctx.unify(&out.0, &TypeInfo::Type(Box::new(t.clone())));
+            t
+        }
+        t => {
+            writeln!(
+                &mut ctx.error_log,
+                "Expected a type in `{tree}`, found a value of type {t}"
+            )
+            .unwrap();
+            return None;
+        }
+    };
+    Some((t, out))
+}
+
+// Type parameters are bound to fresh variables while checking the body, which are then
+// quantified over in the result
+fn with_type_params<'a>(
+    ctx: &mut TypeContext<'a>,
+    decl: &'a SyntaxTree,
+    params: &'a [Rc<str>],
+    f: impl FnOnce(&mut TypeContext<'a>) -> Option<TypedTree>,
+) -> Option<TypedTree> {
+    let mut vars = Vec::new();
+    let mut old_types = Vec::new();
+    for name in params {
+        let var = ctx.fresh_var();
+        let var_t = TypeInfo::Type(Box::new(var.clone()));
+        old_types.push((
+            &name[..],
+            bind(ctx, name, Some(var_t), Declaration::Let(decl)),
+        ));
+        vars.push(var);
+    }
+    let out_opt = f(ctx);
+    for (name, old) in old_types.into_iter().rev() {
+        unbind(ctx, name, old);
+    }
+    let mut ok = true;
+    let mut var_ids = Vec::new();
+    for (name, var) in params.iter().zip(&vars) {
+        match ctx.resolve(var) {
+            TypeInfo::Var(x) if !var_ids.contains(&x) => var_ids.push(x),
+            t => {
+                writeln!(
+                    &mut ctx.error_log,
+                    "Type parameter {name} is constrained to {t}"
+                )
+                .unwrap();
+                ok = false;
+            }
+        }
+    }
+    guard_opt!(ok);
+    let TypedTree(out_t, out) = out_opt?;
+    let out_t = match ctx.resolve(&out_t) {
+        TypeInfo::Type(t) => TypeInfo::Type(Box::new(with_params(*t, vars))),
+        t => t,
+    };
+    Some(TypedTree(TypeInfo::Forall(var_ids, Box::new(out_t)), out))
+}
+
+// Marks a freshly declared struct or enum as taking the given type parameters
+fn with_params(t: TypeInfo, params: Vec<TypeInfo>) -> TypeInfo {
+    match t {
+        TypeInfo::Struct(s) if s.params.is_empty() => TypeInfo::Struct(Rc::new(StructType {
+            params,
+            ..(*s).clone()
+        })),
+        TypeInfo::Enum(e) if e.params.is_empty() => TypeInfo::Enum(Rc::new(EnumType {
+            params,
+            ..(*e).clone()
+        })),
+        t => t,
+    }
+}
+
+// `(Name T...)` on a generic struct or enum gives the type for those arguments
+fn apply_type<'a>(
+    ctx: &mut TypeContext<'a>,
+    func_s: &impl Display,
+    t: &TypeInfo,
+    args: &'a [SyntaxTree],
+) -> Option<TypeInfo> {
+    let params = match t {
+        TypeInfo::Struct(s) => &s.params,
+        TypeInfo::Enum(e) => &e.params,
+        _ => &Vec::new(),
+    };
+    if params.len() != args.len() {
+        writeln!(
+            &mut ctx.error_log,
+            "Type `{func_s}` expects {} type arguments, got {}",
+            params.len(),
+            args.len()
+        )
+        .unwrap();
+        return None;
+    }
+    let mut ok = true;
+    for (param, arg) in params.iter().zip(args) {
+        let arg_t = into_type_expr(ctx, arg).map(|x| x.0);
+        ok &= arg_t.is_some_and(|arg_t| unify_at(ctx, arg, param, &arg_t).is_some());
+    }
+    guard_opt!(ok);
+    Some(ctx.resolve(t))
+}
+
+// Replaces inferred type variables in the finished tree with what they were unified with
+fn resolve_tree(ctx: &TypeContext<'_>, tree: &mut TypedTree) {
+    tree.0 = ctx.resolve(&tree.0);
+    resolve_op(ctx, &mut tree.1);
+}
+
+fn resolve_op(ctx: &TypeContext<'_>, op: &mut TypedOp) {
+    match op {
+        TypedOp::Const(Value::Type(t)) => *t = ctx.resolve(t),
+        TypedOp::Const(_) | TypedOp::LocalGet(_, _) => {}
+        TypedOp::LocalVar(_, _, x, y)
+        | TypedOp::Destructure(_, x, y)
+        | TypedOp::MapT(x, y)
+        | TypedOp::LetRec(_, x, y) => {
+            resolve_tree(ctx, x);
+            resolve_tree(ctx, y);
+        }
+        TypedOp::ArrayT(x, _) | TypedOp::Lambda(_, x) => resolve_tree(ctx, x),
+        TypedOp::Set(place, x) | TypedOp::CompoundSet(_, place, x) => {
+            resolve_place(ctx, place);
+            resolve_tree(ctx, x);
+        }
+        TypedOp::ArrayOp(_, place, items) | TypedOp::MapUpdate(_, place, items) => {
+            resolve_place(ctx, place);
+            for it in items {
+                resolve_tree(ctx, it);
+            }
+        }
+        TypedOp::Arithmetic(_, items)
+        | TypedOp::Seq(items)
+        | TypedOp::StructT(items)
+        | TypedOp::Struct(items)
+        | TypedOp::Variant(_, items)
+        | TypedOp::TupleT(items)
+        | TypedOp::Tuple(items)
+        | TypedOp::Form(_, items)
+        | TypedOp::MapOp(_, items) => {
+            for it in items {
+                resolve_tree(ctx, it);
+            }
+        }
+        TypedOp::Array(items) => {
+            for it in items {
+                resolve_op(ctx, it);
+            }
+        }
+        TypedOp::ArrayGet(x, y) => {
+            resolve_op(ctx, x);
+            resolve_op(ctx, y);
+        }
+        TypedOp::FieldGet(x, _) | TypedOp::TupleGet(x, _) => resolve_op(ctx, x),
+        TypedOp::EnumT(variants) => {
+            for it in variants.iter_mut().flatten() {
+                resolve_tree(ctx, it);
+            }
+        }
+        TypedOp::Match(x, arms) => {
+            resolve_tree(ctx, x);
+            for (_, it) in arms {
+                resolve_tree(ctx, it);
+            }
+        }
+        TypedOp::Map(items) => {
+            for (x, y) in items {
+                resolve_tree(ctx, x);
+                resolve_tree(ctx, y);
+            }
+        }
+        TypedOp::FuncT(items, x) | TypedOp::Call(x, items) => {
+            resolve_tree(ctx, x);
+            for it in items {
+                resolve_tree(ctx, it);
+            }
+        }
+        TypedOp::Quasiquote(x) => resolve_quasi(ctx, x),
+        TypedOp::Raise(x) | TypedOp::Propagate(x) => resolve_tree(ctx, x),
+        TypedOp::Try(body, catches, finally) => {
+            resolve_tree(ctx, body);
+            for it in catches {
+                match it {
+                    TypedCatch::Any(x) => resolve_tree(ctx, x),
+                    TypedCatch::Typed(_, typ, x) => {
+                        resolve_tree(ctx, typ);
+                        resolve_tree(ctx, x);
+                    }
+                }
+            }
+            if let Some(x) = finally {
+                resolve_tree(ctx, x);
+            }
+        }
+    }
+}
+
+fn resolve_quasi(ctx: &TypeContext<'_>, quasi: &mut TypedQuasi) {
+    match quasi {
+        TypedQuasi::Datum(_) => {}
+        TypedQuasi::List(items) => {
+            for it in items {
+                resolve_quasi(ctx, it);
+            }
+        }
+        TypedQuasi::Unquote(x) | TypedQuasi::Splice(x) => resolve_tree(ctx, x),
+    }
+}
+
+fn resolve_place(ctx: &TypeContext<'_>, place: &mut TypedPlace) {
+    match place {
+        TypedPlace::Local(_, _) => {}
+        TypedPlace::Index(x, index) => {
+            resolve_place(ctx, x);
+            resolve_tree(ctx, index);
+        }
+        TypedPlace::Field(x, _) => resolve_place(ctx, x),
+    }
+}
+
+fn into_typed_pattern<'a>(
+    ctx: &mut TypeContext<'a>,
+    pattern: &'a Pattern,
+    typ: Option<&TypeInfo>,
+    bindings: &mut Vec<(&'a str, Option<TypeInfo>)>,
+) -> Option<TypedPattern> {
+    match pattern {
+        Pattern::Wildcard => Some(TypedPattern::Wildcard),
+        Pattern::Bind(name) => {
+            bindings.push((name, typ.cloned()));
+            Some(TypedPattern::Bind(name.clone()))
+        }
+        Pattern::LiteralInt64(x) => {
+            let typ = typ?;
+            if !ctx.unify(typ, &TypeInfo::Int64) {
+                let typ = ctx.resolve(typ);
+                writeln!(
+                    &mut ctx.error_log,
+                    "Pattern {x} cannot match a value of type {typ}"
+                )
+                .unwrap();
+                return None;
+            }
+            Some(TypedPattern::Int64(*x))
+        }
+        Pattern::Variant(name, payload) => {
+            let info_opt = match typ.map(|t| ctx.resolve(t)) {
+                None => None,
+                Some(t) => {
+                    match_ok!(
+                        &mut ctx.error_log,
+                        &t,
+                        TypeInfo::Enum(x) => x.clone(),
+                        format!("Pattern {pattern} cannot match a value of type {t}")
+                    )
+                }
+            };
+            let index_opt = info_opt.as_ref().and_then(|info| {
+                let index = info.variant_index(name);
+                if index.is_none() {
+                    writeln!(&mut ctx.error_log, "Unknown variant {name:?} in pattern").unwrap();
+                }
+                index
+            });
+            let payload_t = match (&info_opt, index_opt) {
+                (Some(info), Some(index)) => {
+                    let payload_t = &info.variants[index].1;
+                    if payload_t.len() == payload.len() {
+                        Some(payload_t)
+                    } else {
+                        writeln!(
+                            &mut ctx.error_log,
+                            "Variant {name:?} expects {} fields in pattern, got {}",
+                            payload_t.len(),
+                            payload.len()
+                        )
+                        .unwrap();
+                        None
+                    }
+                }
+                _ => None,
+            };
+            let mut out_opt = payload_t.map(|_| Vec::new());
+            for (i, it) in payload.iter().enumerate() {
+                let it_t = payload_t.map(|x| &x[i]);
+                let it_opt = into_typed_pattern(ctx, it, it_t, bindings);
+                match (&mut out_opt, it_opt) {
+                    (Some(out), Some(it)) => out.push(it),
+                    _ => out_opt = None,
+                }
+            }
+            Some(TypedPattern::Variant(index_opt?, out_opt?))
+        }
+        Pattern::Tuple(items) => {
+            let typ = typ.map(|t| ctx.resolve(t));
+            if let Some(t @ TypeInfo::Var(_)) = &typ {
+                let tuple_t = TypeInfo::Tuple(items.iter().map(|_| ctx.fresh_var()).collect());
+                ctx.unify(t, &tuple_t);
+            }
+            let typ = typ.map(|t| ctx.resolve(&t));
+            let items_t = match &typ {
+                None => None,
+                Some(t) => {
+                    match_ok!(
+                        &mut ctx.error_log,
+                        t,
+                        TypeInfo::Tuple(x) => x,
+                        format!("Pattern {pattern} cannot match a value of type {t}")
+                    )
+                }
+            };
+            let items_t = match items_t {
+                Some(x) if x.len() != items.len() => {
+                    writeln!(
+                        &mut ctx.error_log,
+                        "Tuple of {} elements matched against pattern of {}",
+                        x.len(),
+                        items.len()
+                    )
+                    .unwrap();
+                    None
+                }
+                x => x,
+            };
+            let mut out_opt = items_t.map(|_| Vec::new());
+            for (i, it) in items.iter().enumerate() {
+                let it_t = items_t.map(|x| &x[i]);
+                let it_opt = into_typed_pattern(ctx, it, it_t, bindings);
+                match (&mut out_opt, it_opt) {
+                    (Some(out), Some(it)) => out.push(it),
+                    _ => out_opt = None,
+                }
+            }
+            Some(TypedPattern::Tuple(out_opt?))
+        }
+    }
+}
+
+pub fn interpret<'a>(ctx: &mut RuntimeContext<'a>, tree: &'a TokenTree) -> Result<Value, String> {
+    match tree {
+        TokenTree::Atom(var) => match ctx.variables.get(&var[..]) {
+            Some(val) => Ok(val.clone()),
+            None => forms::lookup(var)
+                .map(|form| form_closure(&form))
+                .ok_or_else(|| format!("Unknown variable {var:?}")),
+        },
+        TokenTree::Array(arr) => {
+            guard!(!arr.is_empty());
+            match &arr[0] {
+                TokenTree::Atom(s) => match &s[..] {
+                    "+" | "-" | "*" | "/" | "%" => {
+                        guard!(arr.len() >= 2);
+                        let mut acc = interpret(ctx, &arr[1])?;
+                        for x in &arr[2..] {
+                            let y = interpret(ctx, x)?;
+                            acc = broadcast(&mut ctx.raised, s, acc, &y)?;
+                        }
+                        Ok(acc)
+                    }
+                    "let" if matches!(arr.get(1), Some(TokenTree::Array(_))) => {
+                        guard!(arr.len() == 4);
+                        let val = interpret(ctx, &arr[2])?;
+                        let mut bindings = Vec::new();
+                        destructure(&arr[1], val, &mut bindings)?;
+                        let mut old_vals = Vec::new();
+                        for (name, val) in bindings {
+                            old_vals.push((name, ctx.variables.insert(name, val)));
+                        }
+                        let body = interpret(ctx, &arr[3]);
+                        for (name, old_val) in old_vals.into_iter().rev() {
+                            insert_or_remove(&mut ctx.variables, name, old_val);
+                        }
+                        body
+                    }
+                    "let" => {
+                        guard!(arr.len() == 4);
+                        let var = match_ok!(&arr[1], TokenTree::Atom(x) => x)?;
+                        let val = interpret(ctx, &arr[2])?;
+                        let old_val = ctx.variables.insert(var, val);
+                        let body = interpret(ctx, &arr[3]);
+                        insert_or_remove(&mut ctx.variables, var, old_val);
+                        body
+                    }
+                    "var" => {
+                        guard!(arr.len() == 4);
+                        let var = match_ok!(&arr[1], TokenTree::Atom(x) => x)?;
+                        let typ_val = interpret(ctx, &arr[2])?;
+                        let typ = match_ok!(&typ_val, Value::Type(x) => x)?;
+                        let old_val = ctx.variables.insert(var, typ.zero());
+                        let body = interpret(ctx, &arr[3]);
+                        insert_or_remove(&mut ctx.variables, var, old_val);
+                        body
                     }
                     "seq" => {
                         let mut out = Value::Unit;
@@ -295,71 +2200,978 @@ pub fn interpret<'a>(ctx: &mut RuntimeContext<'a>, tree: &'a TokenTree) -> Resul
                     }
                     "set" => {
                         guard!(arr.len() == 3);
-                        let var = match_ok!(&arr[1], TokenTree::Atom(x) => x)?;
+                        let mut path = Vec::new();
+                        let var = eval_place(ctx, &arr[1], &mut path)?;
                         let val = interpret(ctx, &arr[2])?;
-                        let pos = ctx
-                            .variables
-                            .get_mut(&var[..])
-                            .ok_or_else(|| format!("Undeclared variable {var:?}"))?;
-                        *pos = val;
+                        *place_mut(ctx, var, &path)? = val;
+                        Ok(Value::Unit)
+                    }
+                    "+=" | "-=" | "*=" | "/=" | "%=" => {
+                        guard!(arr.len() == 3);
+                        let mut path = Vec::new();
+                        let var = eval_place(ctx, &arr[1], &mut path)?;
+                        let y = match_ok!(interpret(ctx, &arr[2])?, Value::Int64(y) => y)?;
+                        let pos = place_mut(ctx, var, &path)?;
+                        let x = match_ok!(pos, Value::Int64(x) => x)?;
+                        match apply_arithmetic(&s[..s.len() - 1], *x, y) {
+                            Ok(z) => *x = z,
+                            Err(err) => return Err(runtime_error(&mut ctx.raised, err)),
+                        }
                         Ok(Value::Unit)
                     }
+                    "quote" => {
+                        guard!(arr.len() == 2);
+                        Ok(quote_value(&arr[1]))
+                    }
+                    "raise" | "throw" => {
+                        guard!(arr.len() == 2);
+                        let val = interpret(ctx, &arr[1])?;
+                        let err = format!("Uncaught exception {val}");
+                        ctx.raised = Some(val);
+                        Err(err)
+                    }
+                    "?" => {
+                        guard!(arr.len() == 2);
+                        let val = interpret(ctx, &arr[1])?;
+                        // `None` and `Err` are returned as they are, `Some` and `Ok` unwrapped
+                        match val {
+                            Value::Enum(e, i, mut items)
+                                if (e.id, i) == (OPTION_ID, 1) || (e.id, i) == (RESULT_ID, 0) =>
+                            {
+                                Ok(items.swap_remove(0))
+                            }
+                            Value::Enum(e, i, items) if e.id == OPTION_ID || e.id == RESULT_ID => {
+                                ctx.returning = Some(Value::Enum(e, i, items));
+                                Err("`?` outside of a function".into())
+                            }
+                            _ => Err(format!("Cannot use `?` on {val:?}")),
+                        }
+                    }
+                    "try" => {
+                        guard!(arr.len() > 2);
+                        let (catches, finally) = split_finally(&arr[2..])?;
+                        let mut out = interpret(ctx, &arr[1]);
+                        // Kept aside while `finally` runs, which may raise and catch on its own;
+                        // early returns pass through the handlers
+                        let mut returning = ctx.returning.take();
+                        let mut pending = None;
+                        // Only raised values are caught, internal errors propagate untouched
+                        if let (Err(_), None) = (&out, &returning)
+                            && let Some(val) = ctx.raised.take()
+                        {
+                            match find_handler(ctx, catches, &val)? {
+                                Some((var, handler)) => {
+                                    let old_val = var.map(|x| (x, ctx.variables.insert(x, val)));
+                                    out = interpret(ctx, handler);
+                                    if let Some((var, old_val)) = old_val {
+                                        insert_or_remove(&mut ctx.variables, var, old_val);
+                                    }
+                                    pending = ctx.raised.take();
+                                    returning = ctx.returning.take();
+                                }
+                                None => pending = Some(val),
+                            }
+                        }
+                        if let Some(x) = finally {
+                            interpret(ctx, x)?;
+                        }
+                        ctx.raised = pending;
+                        ctx.returning = returning;
+                        out
+                    }
+                    "quasiquote" => {
+                        guard!(arr.len() == 2);
+                        eval_quasi(ctx, &arr[1], 0)
+                    }
                     "array" => {
                         let mut out = Vec::new();
                         for x in &arr[1..] {
                             out.push(interpret(ctx, x)?);
                         }
-                        Ok(Value::Array(out))
+                        Ok(Value::Array(Rc::new(out)))
                     }
                     "array-t" => {
-                        guard!(arr.len() == 2);
+                        guard!(arr.len() == 2 || arr.len() == 3);
                         let inner = match_ok!(interpret(ctx, &arr[1])?, Value::Type(x) => x)?;
-                        Ok(Value::Type(TypeInfo::Array(Box::new(inner))))
+                        let len = match arr.get(2) {
+                            Some(len) => {
+                                Some(match_ok!(len, &TokenTree::Int64(x) if x >= 0 => x as usize)?)
+                            }
+                            None => None,
+                        };
+                        Ok(Value::Type(TypeInfo::Array(Box::new(inner), len)))
                     }
                     "array-get" => {
                         guard!(arr.len() == 3);
-                        let index = match_ok!(interpret(ctx, &arr[1])?, Value::Int64(x) => x)?;
-                        let mut array = match_ok!(interpret(ctx, &arr[2])?, Value::Array(x) => x)?;
-                        guard!(0 <= index && (index as usize) < array.len());
-                        Ok(array.swap_remove(index as usize))
+                        let array = match_ok!(interpret(ctx, &arr[1])?, Value::Array(x) => x)?;
+                        let index = match_ok!(interpret(ctx, &arr[2])?, Value::Int64(x) => x)?;
+                        let len = array.len();
+                        usize::try_from(index)
+                            .ok()
+                            .and_then(|i| array.get(i).cloned())
+                            .ok_or_else(|| {
+                                let err = format!("Index {index} out of bounds for length {len}");
+                                runtime_error(&mut ctx.raised, err)
+                            })
                     }
                     "array-set" => {
                         guard!(arr.len() == 4);
+                        let mut path = Vec::new();
+                        let var = eval_place(ctx, &arr[1], &mut path)?;
+                        let index = match_ok!(interpret(ctx, &arr[2])?, Value::Int64(x) => x)?;
+                        path.push(PlaceStep::Index(index));
                         let val = interpret(ctx, &arr[3])?;
-                        let index = match_ok!(interpret(ctx, &arr[1])?, Value::Int64(x) => x)?;
-                        let var = match_ok!(&arr[2], TokenTree::Atom(x) => x)?;
-                        let array_mut_val = ctx
-                            .variables
-                            .get_mut(&var[..])
-                            .ok_or_else(|| format!("Array {var:?} not found"))?;
-                        let array_mut = match_ok!(array_mut_val, Value::Array(x) => x)?;
-                        guard!(0 <= index && (index as usize) < array_mut.len());
-                        array_mut[index as usize] = val;
+                        *place_mut(ctx, var, &path)? = val;
+                        Ok(Value::Unit)
+                    }
+                    "array-push" | "array-pop" => {
+                        let push = &s[..] == "array-push";
+                        guard!(arr.len() == if push { 3 } else { 2 });
+                        let mut path = Vec::new();
+                        let var = eval_place(ctx, &arr[1], &mut path)?;
+                        let val = if push {
+                            Some(interpret(ctx, &arr[2])?)
+                        } else {
+                            None
+                        };
+                        let array = match_ok!(place_mut(ctx, var, &path)?, Value::Array(x) => x)?;
+                        match val {
+                            Some(val) => {
+                                Rc::make_mut(array).push(val);
+                                Ok(Value::Unit)
+                            }
+                            None => match Rc::make_mut(array).pop() {
+                                Some(val) => Ok(val),
+                                None => {
+                                    let err = "Cannot pop from an empty array".into();
+                                    Err(runtime_error(&mut ctx.raised, err))
+                                }
+                            },
+                        }
+                    }
+                    "struct" => {
+                        let mut fields = Vec::new();
+                        for x in &arr[1..] {
+                            let pair = match_ok!(x, TokenTree::Array(x) if x.len() == 2 => x)?;
+                            let name = match_ok!(&pair[0], TokenTree::Atom(x) => x)?;
+                            guard!(fields.iter().all(|(x, _)| x != name));
+                            let typ = match_ok!(interpret(ctx, &pair[1])?, Value::Type(x) => x)?;
+                            fields.push((name.clone(), typ));
+                        }
+                        let id = ctx.type_count;
+                        ctx.type_count += 1;
+                        let info = Rc::new(StructType {
+                            id,
+                            params: Vec::new(),
+                            fields,
+                        });
+                        Ok(Value::Type(TypeInfo::Struct(info)))
+                    }
+                    "record" => {
+                        guard!(arr.len() > 1);
+                        let typ = match_ok!(interpret(ctx, &arr[1])?, Value::Type(x) => x)?;
+                        let info = match_ok!(typ, TypeInfo::Struct(x) => x)?;
+                        let mut out = vec![None; info.fields.len()];
+                        for x in &arr[2..] {
+                            let pair = match_ok!(x, TokenTree::Array(x) if x.len() == 2 => x)?;
+                            let name = match_ok!(&pair[0], TokenTree::Atom(x) => x)?;
+                            let index = info
+                                .field_index(name)
+                                .ok_or_else(|| format!("Unknown field {name:?}"))?;
+                            guard!(out[index].is_none());
+                            out[index] = Some(interpret(ctx, &pair[1])?);
+                        }
+                        let out = out
+                            .into_iter()
+                            .collect::<Option<Vec<_>>>()
+                            .ok_or("Missing fields in record")?;
+                        Ok(Value::Struct(info, out))
+                    }
+                    "field-get" => {
+                        guard!(arr.len() == 3);
+                        let (info, mut fields) =
+                            match_ok!(interpret(ctx, &arr[1])?, Value::Struct(t, x) => (t, x))?;
+                        let name = match_ok!(&arr[2], TokenTree::Atom(x) => x)?;
+                        let index = info
+                            .field_index(name)
+                            .ok_or_else(|| format!("Unknown field {name:?}"))?;
+                        Ok(fields.swap_remove(index))
+                    }
+                    "field-set" => {
+                        guard!(arr.len() == 4);
+                        let mut path = Vec::new();
+                        let var = eval_place(ctx, &arr[1], &mut path)?;
+                        let name = match_ok!(&arr[2], TokenTree::Atom(x) => x)?;
+                        path.push(PlaceStep::Field(name));
+                        let val = interpret(ctx, &arr[3])?;
+                        *place_mut(ctx, var, &path)? = val;
+                        Ok(Value::Unit)
+                    }
+                    "enum" => {
+                        guard!(arr.len() > 1);
+                        let mut variants = Vec::new();
+                        for x in &arr[1..] {
+                            let variant = match_ok!(x, TokenTree::Array(x) if !x.is_empty() => x)?;
+                            let name = match_ok!(&variant[0], TokenTree::Atom(x) => x)?;
+                            guard!(variants.iter().all(|(x, _)| x != name));
+                            let mut payload = Vec::new();
+                            for y in &variant[1..] {
+                                payload.push(match_ok!(interpret(ctx, y)?, Value::Type(x) => x)?);
+                            }
+                            variants.push((name.clone(), payload));
+                        }
+                        let id = ctx.type_count;
+                        ctx.type_count += 1;
+                        let info = Rc::new(EnumType {
+                            id,
+                            params: Vec::new(),
+                            variants,
+                        });
+                        Ok(Value::Type(TypeInfo::Enum(info)))
+                    }
+                    "variant" => {
+                        guard!(arr.len() > 2);
+                        let typ = match_ok!(interpret(ctx, &arr[1])?, Value::Type(x) => x)?;
+                        let info = match_ok!(typ, TypeInfo::Enum(x) => x)?;
+                        let name = match_ok!(&arr[2], TokenTree::Atom(x) => x)?;
+                        let index = info
+                            .variant_index(name)
+                            .ok_or_else(|| format!("Unknown variant {name:?}"))?;
+                        guard!(arr.len() - 3 == info.variants[index].1.len());
+                        let mut payload = Vec::new();
+                        for x in &arr[3..] {
+                            payload.push(interpret(ctx, x)?);
+                        }
+                        Ok(Value::Enum(info, index, payload))
+                    }
+                    "match" => {
+                        guard!(arr.len() > 2);
+                        let val = interpret(ctx, &arr[1])?;
+                        for x in &arr[2..] {
+                            let arm = match_ok!(x, TokenTree::Array(x) if x.len() == 2 => x)?;
+                            let mut bindings = Vec::new();
+                            if !match_pattern(&arm[0], &val, &mut bindings)? {
+                                continue;
+                            }
+                            let mut old_vals = Vec::new();
+                            for (name, val) in bindings {
+                                old_vals.push((name, ctx.variables.insert(name, val)));
+                            }
+                            let body = interpret(ctx, &arm[1]);
+                            for (name, old_val) in old_vals.into_iter().rev() {
+                                insert_or_remove(&mut ctx.variables, name, old_val);
+                            }
+                            return body;
+                        }
+                        Err("No match arm matched".into())
+                    }
+                    "tuple-t" => {
+                        let mut items = Vec::new();
+                        for x in &arr[1..] {
+                            items.push(match_ok!(interpret(ctx, x)?, Value::Type(x) => x)?);
+                        }
+                        Ok(Value::Type(TypeInfo::Tuple(items)))
+                    }
+                    "tuple" => {
+                        let mut out = Vec::new();
+                        for x in &arr[1..] {
+                            out.push(interpret(ctx, x)?);
+                        }
+                        Ok(Value::Tuple(out))
+                    }
+                    "tuple-get" => {
+                        guard!(arr.len() == 3);
+                        let mut tuple = match_ok!(interpret(ctx, &arr[1])?, Value::Tuple(x) => x)?;
+                        let index = match_ok!(&arr[2], &TokenTree::Int64(x) => x)?;
+                        guard!(0 <= index && (index as usize) < tuple.len());
+                        Ok(tuple.swap_remove(index as usize))
+                    }
+                    "map-t" => {
+                        guard!(arr.len() == 3);
+                        let key = match_ok!(interpret(ctx, &arr[1])?, Value::Type(x) => x)?;
+                        let val = match_ok!(interpret(ctx, &arr[2])?, Value::Type(x) => x)?;
+                        guard!(key.is_hashable());
+                        Ok(Value::Type(TypeInfo::Map(Box::new(key), Box::new(val))))
+                    }
+                    "map" => {
+                        let mut out = ValueMap::default();
+                        for x in &arr[1..] {
+                            let pair = match_ok!(x, TokenTree::Array(x) if x.len() == 2 => x)?;
+                            let key = interpret(ctx, &pair[0])?;
+                            let val = interpret(ctx, &pair[1])?;
+                            out.insert(key, val);
+                        }
+                        Ok(Value::Map(out))
+                    }
+                    "map-get" => {
+                        guard!(arr.len() == 3);
+                        let map = match_ok!(interpret(ctx, &arr[1])?, Value::Map(x) => x)?;
+                        let key = interpret(ctx, &arr[2])?;
+                        map.get(&key).cloned().ok_or_else(|| {
+                            runtime_error(&mut ctx.raised, format!("Key {key} not found"))
+                        })
+                    }
+                    "map-contains" => {
+                        guard!(arr.len() == 3);
+                        let map = match_ok!(interpret(ctx, &arr[1])?, Value::Map(x) => x)?;
+                        let key = interpret(ctx, &arr[2])?;
+                        Ok(Value::Int64(map.contains_key(&key) as i64))
+                    }
+                    "map-len" => {
+                        guard!(arr.len() == 2);
+                        let map = match_ok!(interpret(ctx, &arr[1])?, Value::Map(x) => x)?;
+                        Ok(Value::Int64(map.len() as i64))
+                    }
+                    "map-keys" => {
+                        guard!(arr.len() == 2);
+                        let map = match_ok!(interpret(ctx, &arr[1])?, Value::Map(x) => x)?;
+                        Ok(Value::Array(Rc::new(map.keys().cloned().collect())))
+                    }
+                    "map-insert" | "map-remove" => {
+                        let insert = &s[..] == "map-insert";
+                        guard!(arr.len() == if insert { 4 } else { 3 });
+                        let mut path = Vec::new();
+                        let var = eval_place(ctx, &arr[1], &mut path)?;
+                        let key = interpret(ctx, &arr[2])?;
+                        let val = if insert {
+                            Some(interpret(ctx, &arr[3])?)
+                        } else {
+                            None
+                        };
+                        let map_mut = match_ok!(place_mut(ctx, var, &path)?, Value::Map(x) => x)?;
+                        match val {
+                            Some(val) => map_mut.insert(key, val),
+                            None => map_mut.remove(&key),
+                        };
                         Ok(Value::Unit)
                     }
-                    _ => Err(format!("Unknown function {s}")),
+                    ":" => {
+                        guard!(arr.len() == 3);
+                        let val = interpret(ctx, &arr[1])?;
+                        match_ok!(interpret(ctx, &arr[2])?, Value::Type(_) => ())?;
+                        Ok(val)
+                    }
+                    "fn" => {
+                        guard!(arr.len() == 3);
+                        let params_tree = match_ok!(&arr[1], TokenTree::Array(x) => x)?;
+                        let mut params = Vec::new();
+                        for x in params_tree {
+                            let name = match x {
+                                TokenTree::Array(pair) if pair.len() == 2 => &pair[0],
+                                _ => x,
+                            };
+                            params.push(match_ok!(name, TokenTree::Atom(x) => x.clone())?);
+                        }
+                        let mut atoms = Vec::new();
+                        collect_atoms(&arr[2], &mut atoms);
+                        let mut env: Vec<(Rc<str>, Value)> = Vec::new();
+                        for name in atoms {
+                            if let Some(val) = ctx.variables.get(name)
+                                && env.iter().all(|(x, _)| &x[..] != name)
+                            {
+                                env.push((name.into(), val.clone()));
+                            }
+                        }
+                        Ok(Value::Func(Rc::new(Closure {
+                            name: None,
+                            params,
+                            body: arr[2].clone(),
+                            env,
+                        })))
+                    }
+                    "fn-t" => {
+                        guard!(arr.len() == 3);
+                        let params_tree = match_ok!(&arr[1], TokenTree::Array(x) => x)?;
+                        let mut params = Vec::new();
+                        for x in params_tree {
+                            params.push(match_ok!(interpret(ctx, x)?, Value::Type(x) => x)?);
+                        }
+                        let ret = match_ok!(interpret(ctx, &arr[2])?, Value::Type(x) => x)?;
+                        Ok(Value::Type(TypeInfo::Func(params, Box::new(ret))))
+                    }
+                    "generic" => {
+                        guard!(arr.len() == 3);
+                        let params_tree = match_ok!(&arr[1], TokenTree::Array(x) => x)?;
+                        let mut params = Vec::new();
+                        let mut old_vals = Vec::new();
+                        for x in params_tree {
+                            let name = match_ok!(x, TokenTree::Atom(x) => x)?;
+                            let var = TypeInfo::Var(ctx.type_count);
+                            ctx.type_count += 1;
+                            let old_val = ctx.variables.insert(name, Value::Type(var.clone()));
+                            old_vals.push((&name[..], old_val));
+                            params.push(var);
+                        }
+                        let out = interpret(ctx, &arr[2]);
+                        for (name, old_val) in old_vals.into_iter().rev() {
+                            insert_or_remove(&mut ctx.variables, name, old_val);
+                        }
+                        match out? {
+                            Value::Type(t) => Ok(Value::Type(with_params(t, params))),
+                            val => Ok(val),
+                        }
+                    }
+                    "letrec" => {
+                        guard!(arr.len() == 4);
+                        let var = match_ok!(&arr[1], TokenTree::Atom(x) => x)?;
+                        let closure = match_ok!(interpret(ctx, &arr[2])?, Value::Func(x) => x)?;
+                        let closure = Closure {
+                            name: Some(var.clone()),
+                            ..(*closure).clone()
+                        };
+                        let val = Value::Func(Rc::new(closure));
+                        let old_val = ctx.variables.insert(var, val);
+                        let body = interpret(ctx, &arr[3]);
+                        insert_or_remove(&mut ctx.variables, var, old_val);
+                        body
+                    }
+                    _ if !ctx.variables.contains_key(&s[..])
+                        && let Some(form) = forms::lookup(s) =>
+                    {
+                        guard!(arr.len() - 1 == form.0.arity());
+                        form.0.eval_operands(ctx, &arr[1..])
+                    }
+                    _ => {
+                        let func = ctx
+                            .variables
+                            .get(&s[..])
+                            .cloned()
+                            .ok_or_else(|| format!("Unknown function {s}"))?;
+                        call(ctx, func, &arr[1..])
+                    }
                 },
-                TokenTree::Array(_) => Err("Array used as function".into()),
+                TokenTree::Array(_) => {
+                    let func = interpret(ctx, &arr[0])?;
+                    call(ctx, func, &arr[1..])
+                }
                 TokenTree::Int64(_) => Err("Number used as function".into()),
+                TokenTree::Str(_) => Err("String used as function".into()),
             }
         }
         &TokenTree::Int64(x) => Ok(Value::Int64(x)),
+        TokenTree::Str(x) => Ok(Value::Str(x.clone())),
     }
 }
 
-pub fn interpret_no_context(tree: &TokenTree) -> Result<Value, String> {
+// Raises the message of a runtime error so that `try` can catch it as a string; errors that
+// are not raised this way are bugs of the interpreter and propagate past every handler
+pub fn runtime_error(raised: &mut Option<Value>, err: String) -> String {
+    *raised = Some(Value::Str(err.as_str().into()));
+    err
+}
+
+fn apply_arithmetic(op: &str, x: i64, y: i64) -> Result<i64, String> {
+    match op {
+        "+" => Ok(x.wrapping_add(y)),
+        "-" => Ok(x.wrapping_sub(y)),
+        "*" => Ok(x.wrapping_mul(y)),
+        _ if y == 0 => Err("Division by zero".into()),
+        "/" => Ok(x.wrapping_div(y)),
+        "%" => Ok(x.wrapping_rem(y)),
+        _ => Err(format!("Unknown operator {op}")),
+    }
+}
+
+// Applies the operator elementwise, repeating scalars across arrays; the left operand is
+// updated in place when nothing else shares it
+fn broadcast(raised: &mut Option<Value>, op: &str, x: Value, y: &Value) -> Result<Value, String> {
+    match (x, y) {
+        (Value::Int64(x), &Value::Int64(y)) => apply_arithmetic(op, x, y)
+            .map(Value::Int64)
+            .map_err(|err| runtime_error(raised, err)),
+        (Value::Array(mut xs), Value::Array(ys)) => {
+            if xs.len() != ys.len() {
+                let (x_len, y_len) = (xs.len(), ys.len());
+                let err = format!("Array lengths {x_len} and {y_len} do not match");
+                return Err(runtime_error(raised, err));
+            }
+            for (x, y) in Rc::make_mut(&mut xs).iter_mut().zip(ys.iter()) {
+                *x = broadcast(raised, op, std::mem::take(x), y)?;
+            }
+            Ok(Value::Array(xs))
+        }
+        (Value::Array(mut xs), y) => {
+            for x in Rc::make_mut(&mut xs).iter_mut() {
+                *x = broadcast(raised, op, std::mem::take(x), y)?;
+            }
+            Ok(Value::Array(xs))
+        }
+        (x, Value::Array(ys)) => {
+            let mut out = Vec::with_capacity(ys.len());
+            for y in ys.iter() {
+                out.push(broadcast(raised, op, x.clone(), y)?);
+            }
+            Ok(Value::Array(Rc::new(out)))
+        }
+        (x, y) => Err(format!("Cannot apply {op} to {x:?} and {y:?}")),
+    }
+}
+
+// Total order on the values `array-sort` accepts; anything else compares equal
+pub fn compare_values(x: &Value, y: &Value) -> Ordering {
+    match (x, y) {
+        (Value::Int64(x), Value::Int64(y)) => x.cmp(y),
+        (Value::Str(x), Value::Str(y)) => x.cmp(y),
+        (Value::Array(xs), Value::Array(ys)) => compare_lists(xs, ys),
+        (Value::Tuple(xs), Value::Tuple(ys)) => compare_lists(xs, ys),
+        _ => Ordering::Equal,
+    }
+}
+
+fn compare_lists(xs: &[Value], ys: &[Value]) -> Ordering {
+    let mut ord = xs.iter().zip(ys).map(|(x, y)| compare_values(x, y));
+    ord.find(|x| x.is_ne()).unwrap_or(xs.len().cmp(&ys.len()))
+}
+
+fn split_finally(clauses: &[TokenTree]) -> Result<(&[TokenTree], Option<&TokenTree>), String> {
+    match clauses.split_last() {
+        Some((TokenTree::Array(last), rest)) if matches!(last.first(), Some(TokenTree::Atom(x)) if &x[..] == "finally") =>
+        {
+            guard!(last.len() == 2);
+            Ok((rest, Some(&last[1])))
+        }
+        _ => Ok((clauses, None)),
+    }
+}
+
+// Returns the first `catch` clause accepting the value, with the variable it binds
+fn find_handler<'a>(
+    ctx: &mut RuntimeContext<'a>,
+    catches: &'a [TokenTree],
+    val: &Value,
+) -> Result<Option<(Option<&'a str>, &'a TokenTree)>, String> {
+    for it in catches {
+        let items = match_ok!(it, TokenTree::Array(x) if x.len() == 3 => x)?;
+        match &items[1] {
+            TokenTree::Atom(x) if &x[..] == "_" => return Ok(Some((None, &items[2]))),
+            TokenTree::Array(binding) => {
+                guard!(binding.len() == 2);
+                let var = match_ok!(&binding[0], TokenTree::Atom(x) => x)?;
+                let t = match_ok!(interpret(ctx, &binding[1])?, Value::Type(x) => x)?;
+                if has_type(val, &t) {
+                    return Ok(Some((Some(var), &items[2])));
+                }
+            }
+            _ => return Err(format!("Malformed handler {it}")),
+        }
+    }
+    Ok(None)
+}
+
+// Runtime type test for `catch`; type variables match anything
+pub fn has_type(val: &Value, t: &TypeInfo) -> bool {
+    match (val, t) {
+        (_, TypeInfo::Var(_)) => true,
+        (Value::Symbol(_) | Value::List(_) | Value::Int64(_) | Value::Str(_), TypeInfo::Sexp) => {
+            true
+        }
+        (Value::Unit, TypeInfo::Unit)
+        | (Value::Int64(_), TypeInfo::Int64)
+        | (Value::Str(_), TypeInfo::Str)
+        | (Value::Type(_), TypeInfo::Type(_))
+        | (Value::Map(_), TypeInfo::Map(_, _))
+        | (Value::Func(_), TypeInfo::Func(_, _)) => true,
+        (Value::Array(xs), TypeInfo::Array(t, len)) => {
+            len.is_none_or(|n| n == xs.len()) && xs.iter().all(|x| has_type(x, t))
+        }
+        (Value::Tuple(xs), TypeInfo::Tuple(ts)) => {
+            xs.len() == ts.len() && xs.iter().zip(ts).all(|(x, t)| has_type(x, t))
+        }
+        (Value::Struct(s, _), TypeInfo::Struct(t)) => s.id == t.id,
+        (Value::Enum(e, _, _), TypeInfo::Enum(t)) => e.id == t.id,
+        _ => false,
+    }
+}
+
+pub fn quote_value(tree: &TokenTree) -> Value {
+    match tree {
+        TokenTree::Atom(x) => Value::Symbol(x.clone()),
+        TokenTree::Int64(x) => Value::Int64(*x),
+        TokenTree::Str(x) => Value::Str(x.clone()),
+        TokenTree::Array(items) => Value::List(Rc::new(items.iter().map(quote_value).collect())),
+    }
+}
+
+pub fn unquote_value(val: &Value) -> Result<TokenTree, String> {
+    match val {
+        Value::Symbol(x) => Ok(TokenTree::Atom(x.clone())),
+        Value::Int64(x) => Ok(TokenTree::Int64(*x)),
+        Value::Str(x) => Ok(TokenTree::Str(x.clone())),
+        Value::List(items) => Ok(TokenTree::Array(
+            items.iter().map(unquote_value).collect::<Result<_, _>>()?,
+        )),
+        _ => Err(format!("Cannot use {val:?} as code")),
+    }
+}
+
+fn quasi_head(tree: &TokenTree) -> Option<(&str, &TokenTree)> {
+    match tree {
+        TokenTree::Array(items) if items.len() == 2 => match &items[0] {
+            TokenTree::Atom(head)
+                if matches!(&head[..], "quasiquote" | "unquote" | "unquote-splicing") =>
+            {
+                Some((head, &items[1]))
+            }
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn eval_quasi<'a>(
+    ctx: &mut RuntimeContext<'a>,
+    tree: &'a TokenTree,
+    depth: usize,
+) -> Result<Value, String> {
+    match (quasi_head(tree), tree) {
+        (Some(("unquote", x)), _) if depth == 0 => interpret(ctx, x),
+        (Some(("unquote-splicing", _)), _) if depth == 0 => {
+            Err(format!("Cannot splice outside of a list in `{tree}`"))
+        }
+        (Some((head, x)), _) => {
+            let depth = if head == "quasiquote" {
+                depth + 1
+            } else {
+                depth - 1
+            };
+            let x = eval_quasi(ctx, x, depth)?;
+            Ok(Value::List(Rc::new(vec![Value::Symbol(head.into()), x])))
+        }
+        (None, TokenTree::Array(items)) => {
+            let mut out = Vec::new();
+            for it in items {
+                match quasi_head(it) {
+                    Some(("unquote-splicing", x)) if depth == 0 => match interpret(ctx, x)? {
+                        Value::List(xs) | Value::Array(xs) => out.extend(xs.iter().cloned()),
+                        val => return Err(format!("Cannot splice {val:?} in `{tree}`")),
+                    },
+                    _ => out.push(eval_quasi(ctx, it, depth)?),
+                }
+            }
+            Ok(Value::List(Rc::new(out)))
+        }
+        (None, _) => Ok(quote_value(tree)),
+    }
+}
+
+enum PlaceStep<'a> {
+    Index(i64),
+    Field(&'a str),
+}
+
+// Evaluates the indices along a place expression and returns the variable it starts from
+fn eval_place<'a>(
+    ctx: &mut RuntimeContext<'a>,
+    tree: &'a TokenTree,
+    path: &mut Vec<PlaceStep<'a>>,
+) -> Result<&'a str, String> {
+    let arr = match tree {
+        TokenTree::Atom(x) => return Ok(x),
+        TokenTree::Array(arr) if arr.len() == 3 => arr,
+        _ => return Err(format!("Cannot assign to `{tree}`")),
+    };
+    let head = match_ok!(&arr[0], TokenTree::Atom(x) => x)?;
+    let var = eval_place(ctx, &arr[1], path)?;
+    match &head[..] {
+        "array-get" => {
+            let index = match_ok!(interpret(ctx, &arr[2])?, Value::Int64(x) => x)?;
+            path.push(PlaceStep::Index(index));
+        }
+        "field-get" => path.push(PlaceStep::Field(
+            match_ok!(&arr[2], TokenTree::Atom(x) => x)?,
+        )),
+        _ => return Err(format!("Cannot assign to `{tree}`")),
+    }
+    Ok(var)
+}
+
+fn place_mut<'c>(
+    ctx: &'c mut RuntimeContext<'_>,
+    var: &str,
+    path: &[PlaceStep],
+) -> Result<&'c mut Value, String> {
+    let RuntimeContext {
+        variables, raised, ..
+    } = ctx;
+    let mut pos = variables
+        .get_mut(var)
+        .ok_or_else(|| format!("Undeclared variable {var:?}"))?;
+    for step in path {
+        pos = match (step, pos) {
+            (&PlaceStep::Index(index), Value::Array(items)) => {
+                let len = items.len();
+                usize::try_from(index)
+                    .ok()
+                    .filter(|&i| i < len)
+                    .map(|i| &mut Rc::make_mut(items)[i])
+                    .ok_or_else(|| {
+                        let err = format!("Index {index} out of bounds for length {len}");
+                        runtime_error(raised, err)
+                    })?
+            }
+            (PlaceStep::Field(name), Value::Struct(info, fields)) => {
+                let index = info
+                    .field_index(name)
+                    .ok_or_else(|| format!("Unknown field {name:?}"))?;
+                &mut fields[index]
+            }
+            (_, pos) => return Err(format!("Cannot assign inside `{pos}`")),
+        };
+    }
+    Ok(pos)
+}
+
+// Function value that applies the form to its arguments
+fn form_closure(form: &Form) -> Value {
+    let params: Vec<Rc<str>> = (0..form.0.arity())
+        .map(|i| format!("x{i}").into())
+        .collect();
+    let head = TokenTree::Atom(form.0.name().into());
+    let args = params.iter().map(|x| TokenTree::Atom(x.clone()));
+    Value::Func(Rc::new(Closure {
+        name: None,
+        params: params.clone(),
+        body: TokenTree::Array([head].into_iter().chain(args).collect()),
+        env: Vec::new(),
+    }))
+}
+
+fn call<'a>(
+    ctx: &mut RuntimeContext<'a>,
+    func: Value,
+    args: &'a [TokenTree],
+) -> Result<Value, String> {
+    let closure = match func {
+        Value::Type(t) => return apply_type_value(ctx, t, args),
+        func => match_ok!(func, Value::Func(x) => x)?,
+    };
+    guard!(closure.params.len() == args.len());
+    let mut vals = Vec::new();
+    for x in args {
+        vals.push(interpret(ctx, x)?);
+    }
+    call_closure(ctx, &closure, vals)
+}
+
+// Calls a function value on already evaluated arguments
+pub fn call_values(
+    ctx: &mut RuntimeContext<'_>,
+    func: Value,
+    vals: Vec<Value>,
+) -> Result<Value, String> {
+    let closure = match_ok!(func, Value::Func(x) => x)?;
+    guard!(closure.params.len() == vals.len());
+    call_closure(ctx, &closure, vals)
+}
+
+fn call_closure(
+    ctx: &mut RuntimeContext<'_>,
+    closure: &Rc<Closure>,
+    vals: Vec<Value>,
+) -> Result<Value, String> {
+    let mut inner = RuntimeContext {
+        type_count: ctx.type_count,
+        ..Default::default()
+    };
+    for (name, val) in &closure.env {
+        inner.variables.insert(name, val.clone());
+    }
+    if let Some(name) = &closure.name {
+        inner.variables.insert(name, Value::Func(closure.clone()));
+    }
+    for (name, val) in closure.params.iter().zip(vals) {
+        inner.variables.insert(name, val);
+    }
+    let out = interpret(&mut inner, &closure.body);
+    ctx.type_count = inner.type_count;
+    ctx.raised = inner.raised;
+    match inner.returning {
+        Some(val) => Ok(val),
+        None => out,
+    }
+}
+
+fn apply_type_value<'a>(
+    ctx: &mut RuntimeContext<'a>,
+    t: TypeInfo,
+    args: &'a [TokenTree],
+) -> Result<Value, String> {
+    let params = match &t {
+        TypeInfo::Struct(s) => &s.params,
+        TypeInfo::Enum(e) => &e.params,
+        _ => return Err("Type used as function".into()),
+    };
+    guard!(params.len() == args.len());
+    let mut map = Vec::new();
+    for (param, x) in params.iter().zip(args) {
+        let param = match_ok!(param, &TypeInfo::Var(x) => x)?;
+        map.push((param, match_ok!(interpret(ctx, x)?, Value::Type(x) => x)?));
+    }
+    Ok(Value::Type(t.substitute(&map)))
+}
+
+fn collect_atoms<'t>(tree: &'t TokenTree, out: &mut Vec<&'t str>) {
+    match tree {
+        TokenTree::Atom(x) => out.push(x),
+        TokenTree::Array(arr) => {
+            for x in arr {
+                collect_atoms(x, out);
+            }
+        }
+        TokenTree::Int64(_) | TokenTree::Str(_) => {}
+    }
+}
+
+fn match_pattern<'a>(
+    pattern: &'a TokenTree,
+    val: &Value,
+    bindings: &mut Vec<(&'a str, Value)>,
+) -> Result<bool, String> {
+    match pattern {
+        TokenTree::Atom(x) if &x[..] == "_" => Ok(true),
+        TokenTree::Atom(x) => {
+            bindings.push((x, val.clone()));
+            Ok(true)
+        }
+        &TokenTree::Int64(x) => Ok(matches!(val, &Value::Int64(y) if x == y)),
+        TokenTree::Str(_) => Err("String used as pattern".into()),
+        TokenTree::Array(arr) if matches!(arr.first(), Some(TokenTree::Atom(x)) if &x[..] == "tuple") =>
+        {
+            let items = match_ok!(val, Value::Tuple(x) => x)?;
+            guard!(arr.len() - 1 == items.len());
+            for (x, y) in arr[1..].iter().zip(items) {
+                if !match_pattern(x, y, bindings)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        TokenTree::Array(arr) => {
+            guard!(!arr.is_empty());
+            let name = match_ok!(&arr[0], TokenTree::Atom(x) => x)?;
+            let (info, index, payload) = match_ok!(val, Value::Enum(t, i, x) => (t, *i, x))?;
+            if info.variants[index].0 != *name {
+                return Ok(false);
+            }
+            guard!(arr.len() - 1 == payload.len());
+            for (x, y) in arr[1..].iter().zip(payload) {
+                if !match_pattern(x, y, bindings)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+    }
+}
+
+fn destructure<'a>(
+    pattern: &'a TokenTree,
+    val: Value,
+    bindings: &mut Vec<(&'a str, Value)>,
+) -> Result<(), String> {
+    match pattern {
+        TokenTree::Atom(x) if &x[..] == "_" => Ok(()),
+        TokenTree::Atom(x) => {
+            bindings.push((x, val));
+            Ok(())
+        }
+        TokenTree::Array(arr) => {
+            let items = match_ok!(val, Value::Tuple(x) => x)?;
+            guard!(arr.len() == items.len());
+            for (x, y) in arr.iter().zip(items) {
+                destructure(x, y, bindings)?;
+            }
+            Ok(())
+        }
+        TokenTree::Int64(_) => Err("Number used as let binding".into()),
+        TokenTree::Str(_) => Err("String used as let binding".into()),
+    }
+}
+
+fn global_runtime_context<'a>() -> RuntimeContext<'a> {
     let mut ctx = RuntimeContext::default();
     ctx.variables.insert("i64", Value::Type(TypeInfo::Int64));
-    interpret(&mut ctx, tree)
+    ctx.variables.insert("str", Value::Type(TypeInfo::Str));
+    ctx.variables.insert("sexp", Value::Type(TypeInfo::Sexp));
+    let option = option_type(TypeInfo::Var(0));
+    let result = result_type(TypeInfo::Var(0), TypeInfo::Var(1));
+    ctx.variables.insert("Option", Value::Type(option));
+    ctx.variables.insert("Result", Value::Type(result));
+    ctx.type_count = BUILTIN_TYPE_COUNT;
+    ctx
+}
+
+// Interprets in a fresh context, handing out the value of a `raise` that is left uncaught
+fn interpret_global(tree: &TokenTree, raised: &mut Option<Value>) -> Result<Value, String> {
+    let resolved = modules::resolve(tree)?;
+    let expanded = macros::expand(&resolved)?;
+    let mut ctx = global_runtime_context();
+    let out = interpret(&mut ctx, &expanded);
+    if out.is_err() {
+        *raised = ctx.raised;
+    }
+    out
+}
+
+pub fn interpret_no_context(tree: &TokenTree) -> Result<Value, String> {
+    interpret_global(tree, &mut None)
+}
+
+fn global_type_context<'a>() -> TypeContext<'a> {
+    let mut ctx = TypeContext::default();
+    ctx.variables
+        .insert("i64", Some(TypeInfo::Type(Box::new(TypeInfo::Int64))));
+    ctx.variables
+        .insert("str", Some(TypeInfo::Type(Box::new(TypeInfo::Str))));
+    ctx.variables
+        .insert("sexp", Some(TypeInfo::Type(Box::new(TypeInfo::Sexp))));
+    let vars = vec![ctx.substitution.len(), ctx.substitution.len() + 1];
+    let (t, e) = (ctx.fresh_var(), ctx.fresh_var());
+    let option = TypeInfo::Type(Box::new(option_type(t.clone())));
+    let result = TypeInfo::Type(Box::new(result_type(t, e)));
+    ctx.variables.insert(
+        "Option",
+        Some(TypeInfo::Forall(vars[..1].to_vec(), Box::new(option))),
+    );
+    ctx.variables
+        .insert("Result", Some(TypeInfo::Forall(vars, Box::new(result))));
+    ctx.type_count = BUILTIN_TYPE_COUNT;
+    ctx
+}
+
+// Runs quoted code as a separate program that sees none of the caller's variables
+// Raised values and runtime errors of the evaluated code are raised again in the caller's
+// context, so that its `try` can catch them
+pub fn eval_value(
+    ctx: &mut RuntimeContext<'_>,
+    code: &Value,
+    expected: &TypeInfo,
+) -> Result<Value, String> {
+    let tree = unquote_value(code)?;
+    let syntax = SyntaxTree::try_from(&tree)
+        .map_err(|err| format!("Cannot evaluate `{tree}`: {}", err.trim_end()))?;
+    let mut type_ctx = global_type_context();
+    // Type variables left in the expected type stay unknown
+    let mut vars = Vec::new();
+    expected.free_vars(&mut vars);
+    let count = vars.iter().map(|&x| x + 1).max().unwrap_or(0);
+    type_ctx.substitution.resize(count, None);
+    if check_typed_tree(&mut type_ctx, &syntax, expected).is_none() {
+        let err = type_ctx.error_log.trim_end();
+        return Err(format!("Cannot evaluate `{tree}`: {err}"));
+    }
+    interpret_global(&tree, &mut ctx.raised)
 }
 
 impl TryFrom<&SyntaxTree> for TypedTree {
     type Error = String;
     fn try_from(value: &SyntaxTree) -> Result<Self, Self::Error> {
-        let mut ctx = TypeContext::default();
-        ctx.variables
-            .insert("i64", Some(TypeInfo::Type(Box::new(TypeInfo::Int64))));
-        into_typed_tree(&mut ctx, value).ok_or(ctx.error_log)
+        let mut ctx = global_type_context();
+        let Some(mut out) = into_typed_tree(&mut ctx, value) else {
+            return Err(ctx.error_log);
+        };
+        resolve_tree(&ctx, &mut out);
+        Ok(out)
     }
 }
 
@@ -369,3 +3181,119 @@ pub fn parse_interpret(s: &str) -> Result<String, String> {
     let tree3 = TypedTree::try_from(&tree2)?;
     Ok(format!("{tree3:?}"))
 }
+
+// Run with `cargo test --release -- --ignored --nocapture`; reads and writes should cost the
+// same regardless of the array length, which is checked against copying the array each time
+#[cfg(test)]
+mod bench {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    const LENGTHS: [usize; 4] = [500, 1000, 2000, 4000];
+    // Each length keeps its fastest run, which is the least disturbed by the machine
+    const RUNS: usize = 5;
+
+    // Loops are written as recursion, which needs a deeper stack than test threads get
+    fn run(src: String) -> i64 {
+        let thread = std::thread::Builder::new()
+            .stack_size(1 << 30)
+            .spawn(move || {
+                let tree: TokenTree = src.parse().unwrap();
+                match interpret_no_context(&tree) {
+                    Ok(Value::Int64(x)) => x,
+                    other => panic!("{other:?}"),
+                }
+            });
+        thread.unwrap().join().unwrap()
+    }
+
+    fn array_literal(len: usize) -> String {
+        let items: Vec<_> = (0..len).map(|x| x.to_string()).collect();
+        format!("(array {})", items.join(" "))
+    }
+
+    // Runs the program made for each length, which does that many operations, and returns
+    // how much more an operation costs on the longest array than on the shortest
+    fn growth(name: &str, program: impl Fn(usize) -> (String, i64)) -> f64 {
+        let mut per_op = Vec::new();
+        for len in LENGTHS {
+            let (src, expected) = program(len);
+            let mut elapsed = Duration::MAX;
+            for _ in 0..RUNS {
+                let start = Instant::now();
+                let out = run(src.clone());
+                elapsed = elapsed.min(start.elapsed());
+                assert_eq!(out, expected);
+            }
+            println!(
+                "{name} x{len}: {elapsed:?} ({:?} per op)",
+                elapsed / len as u32
+            );
+            per_op.push(elapsed / len as u32);
+        }
+        let (first, last) = (per_op[0], per_op[LENGTHS.len() - 1]);
+        last.as_secs_f64() / first.max(Duration::from_nanos(1)).as_secs_f64()
+    }
+
+    // The baseline copies the array on every operation, as reads did before arrays were shared,
+    // so its cost grows with the length; ours should stay within a small factor of flat and
+    // grow well below the baseline
+    fn check_flat(name: &str, shared: f64, copied: f64) {
+        let len_growth = LENGTHS[LENGTHS.len() - 1] / LENGTHS[0];
+        println!(
+            "{name}: per-op cost grows x{shared:.2} shared, x{copied:.2} copied, lengths x{len_growth}"
+        );
+        assert!(
+            shared < 2.5,
+            "{name} per-op cost grew x{shared:.2} from length {} to {}",
+            LENGTHS[0],
+            LENGTHS[LENGTHS.len() - 1]
+        );
+        assert!(
+            shared * 2.0 < copied,
+            "{name} per-op cost grew x{shared:.2}, not clearly below x{copied:.2} when copying"
+        );
+    }
+
+    fn sum_program(len: usize, read: &str) -> (String, i64) {
+        let src = format!(
+            "(let a {} (letrec sum (fn (i acc) (match i (0 acc) (_ (sum (- i 1) (+ acc (array-get {read} (- i 1))))))) (sum {len} 0)))",
+            array_literal(len),
+        );
+        (src, (len * (len - 1) / 2) as i64)
+    }
+
+    fn increment_program(len: usize, write: &str) -> (String, i64) {
+        let src = format!(
+            "(var a (array-t i64) (seq (set a {}) {} (array-get a 0)))",
+            array_literal(len),
+            write.repeat(len),
+        );
+        (src, len as i64)
+    }
+
+    #[test]
+    #[ignore]
+    fn array_reads() {
+        let shared = growth("array-get", |len| sum_program(len, "a"));
+        let copied = growth("array-get copied", |len| {
+            sum_program(len, "(array-concat a (array))")
+        });
+        check_flat("array-get", shared, copied);
+    }
+
+    #[test]
+    #[ignore]
+    fn array_writes() {
+        let shared = growth("array-set", |len| {
+            increment_program(len, "(+= (array-get a 0) 1) ")
+        });
+        let copied = growth("array-set copied", |len| {
+            increment_program(
+                len,
+                "(set a (array-concat a (array))) (+= (array-get a 0) 1) ",
+            )
+        });
+        check_flat("array-set", shared, copied);
+    }
+}
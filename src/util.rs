@@ -30,17 +30,13 @@ macro_rules! guard {
         })?
     };
 
-    ($logger:expr, $e:expr) => {
+    // Logs a message for the user, which must not point into the interpreter source
+    ($logger:expr, $e:expr, $msg:expr) => {
         (if $e {
             Some(())
         } else {
-            $logger.push_str(concat!(
-                "Guard fail ",
-                stringify!($e),
-                " at ",
-                $crate::source_loc!(),
-                "\n",
-            ));
+            $logger.push_str(&$msg);
+            $logger.push('\n');
             None
         })?
     };
@@ -63,19 +59,12 @@ macro_rules! match_ok {
         }
     };
 
-    ($logger:expr, $e:expr, $p:pat $(if $g:expr)? => $r:expr) => {
+    ($logger:expr, $e:expr, $p:pat $(if $g:expr)? => $r:expr, $msg:expr) => {
         match $e {
             $p $(if $g)? => Some($r),
             _ => {
-                $logger.push_str(concat!(
-                    "Match fail: ",
-                    stringify!($e),
-                    " as ",
-                    stringify!($p),
-                    " at ",
-                    $crate::source_loc!(),
-                    "\n",
-                ));
+                $logger.push_str(&$msg);
+                $logger.push('\n');
                 None
             }
         }
@@ -0,0 +1,2 @@
+(let x 7
+  (tuple (+ x 3) (- x 10) (* x x) (/ x 2) (% x 4)))
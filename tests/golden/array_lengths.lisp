@@ -0,0 +1,7 @@
+(tuple
+  (array-len (array (array 1) (array 1 2)))
+  (map-len (map (1 (array 1)) (2 (array 1 2))))
+  (array (array 1 2) (array 3 4))
+  (match 1 (0 (array 1)) (_ (array 1 2)))
+  (try (array 1 2) (catch _ (array 3 4)))
+  (+ (array 1 2) (array-slice (array 3 4 5) 0 2)))
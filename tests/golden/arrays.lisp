@@ -0,0 +1,6 @@
+(var a (array-t i64)
+  (seq
+    (array-push a 3)
+    (array-push a 1)
+    (array-push a 2)
+    (tuple (array-len a) (array-sort a) (array-reverse a) (array-slice a 1 3))))
@@ -0,0 +1,2 @@
+(let square (fn (x) (* x x))
+  (seq (assert (square 2)) (assert-eq (square 3) 9) (assert-eq (square 2) 5)))
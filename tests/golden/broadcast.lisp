@@ -0,0 +1,3 @@
+(tuple
+  (+ (array 1 2 3) 10)
+  (* 2 (array (array 1 2) (array 3 4))))
@@ -0,0 +1,3 @@
+(let compose (fn (f g) (fn (x) (f (g x))))
+  (let k 10
+    ((compose (fn (x) (+ x k)) (fn (x) (* x 2))) 5)))
@@ -0,0 +1,8 @@
+(let Shape (enum (Circle i64) (Rect i64 i64) (Empty))
+  (let area
+    (fn ((s Shape))
+      (match s
+        ((Circle r) (* 3 (* r r)))
+        ((Rect w h) (* w h))
+        ((Empty) 0)))
+    (array-map area (array (variant Shape Circle 2) (variant Shape Rect 3 4) (variant Shape Empty)))))
@@ -0,0 +1 @@
+(var t (tuple-t (array-t i64) i64) (seq (array-push (tuple-get t 0) 1) (array-len (tuple-get t 0))))
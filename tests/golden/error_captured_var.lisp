@@ -0,0 +1 @@
+(var x i64 (let f (fn () (set x 5)) (seq (f) x)))
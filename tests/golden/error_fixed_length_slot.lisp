@@ -0,0 +1 @@
+(var a (array-t i64 3) (seq (set a (array-slice (array 1 2 3 4 5) 0 1)) (array-len a)))
@@ -0,0 +1,4 @@
+(defmacro count-args
+  (syntax-rules ()
+    ((_ ... x) x))
+  (count-args 1 2 3))
@@ -0,0 +1 @@
+(map-insert (map (1 2)) 3 4)
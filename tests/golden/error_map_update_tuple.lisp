@@ -0,0 +1 @@
+(var t (tuple-t (map-t i64 i64) i64) (seq (map-insert (tuple-get t 0) 1 1) (map-len (tuple-get t 0))))
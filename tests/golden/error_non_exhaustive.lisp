@@ -0,0 +1 @@
+(let O (enum (None) (Some i64)) (match (variant O Some 5) ((Some x) x)))
@@ -0,0 +1 @@
+(let f (fn (o) (seq (? o) (raise "unreachable"))) 0)
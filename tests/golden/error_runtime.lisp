@@ -0,0 +1,2 @@
+(let get (fn (xs i) (array-get xs i))
+  (get (array 1 2 3) 3))
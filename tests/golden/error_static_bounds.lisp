@@ -0,0 +1 @@
+(let a (array 1 2 3) (array-get a 3))
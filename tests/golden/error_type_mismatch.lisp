@@ -0,0 +1 @@
+(let f (fn (x) (+ x 1)) (f "s"))
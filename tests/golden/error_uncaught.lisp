@@ -0,0 +1 @@
+(seq (raise 42) 0)
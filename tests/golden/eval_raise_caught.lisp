@@ -0,0 +1,4 @@
+(tuple
+  (try (eval (quote (raise 5)) i64) (catch (x i64) (+ x 1)))
+  (try (eval (quote (/ 1 0)) i64) (catch (e str) 0))
+  (try (eval (quote (map-get (map (1 2)) 3)) i64) (catch _ -1)))
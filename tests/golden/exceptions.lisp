@@ -0,0 +1,6 @@
+(let safe-div
+  (fn (a b)
+    (try
+      (match b (0 (raise "division by zero")) (_ (/ a b)))
+      (catch (e str) -1)))
+  (tuple (safe-div 10 2) (safe-div 1 0)))
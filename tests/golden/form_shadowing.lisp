@@ -0,0 +1,8 @@
+(let all (fn (a) a)
+  (let range (fn (x y) (* x y))
+    (tuple
+      (all 1)
+      (range 6 7)
+      (array-map array-len (array (array 1 2 3) (array 4 5 6)))
+      (array-map array-reverse (array (array 1 2) (array 3 4)))
+      (let f enumerate (f (array 5 6))))))
@@ -0,0 +1,3 @@
+(let Pair (generic (A B) (struct (fst A) (snd B)))
+  (let swap (generic (A B) (fn ((p (Pair A B))) (record (Pair B A) (fst (field-get p snd)) (snd (field-get p fst)))))
+    (swap (record (Pair i64 str) (fst 1) (snd "one")))))
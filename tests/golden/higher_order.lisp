@@ -0,0 +1,5 @@
+(tuple
+  (array-map (fn (x) (* x x)) (range 0 5))
+  (filter (fn (x) (% x 2)) (range 0 10))
+  (fold (fn (acc x) (+ acc x)) 0 (range 1 11))
+  (zip (range 0 3) (array "a" "b")))
@@ -0,0 +1,2 @@
+(let id (fn (x) x)
+  (tuple (id 1) (id "a") (id (array 2))))
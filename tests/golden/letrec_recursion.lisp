@@ -0,0 +1,2 @@
+(letrec fact (fn (n) (match n (0 1) (k (* k (fact (- k 1))))))
+  (fact 10))
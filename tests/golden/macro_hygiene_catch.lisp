@@ -0,0 +1,4 @@
+(defmacro add-one
+  (syntax-rules ()
+    ((_ e) (try (raise 1) (catch (x i64) (+ x e)))))
+  (let x 100 (add-one x)))
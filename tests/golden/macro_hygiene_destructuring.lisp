@@ -0,0 +1,4 @@
+(defmacro add-one
+  (syntax-rules ()
+    ((_ e) (let (x _) (tuple 1 0) (+ x e))))
+  (let x 100 (add-one x)))
@@ -0,0 +1,4 @@
+(defmacro add-one
+  (syntax-rules ()
+    ((_ e) (match (tuple 1 0) ((tuple x _) (+ x e)))))
+  (let x 100 (add-one x)))
@@ -0,0 +1,6 @@
+(defmacro my-or
+  (syntax-rules ()
+    ((_) 0)
+    ((_ e) e)
+    ((_ e r ...) (let t e (match t (0 (my-or r ...)) (_ t)))))
+  (let t 5 (my-or 0 0 t)))
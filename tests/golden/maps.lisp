@@ -0,0 +1,6 @@
+(var m (map-t str i64)
+  (seq
+    (set m (map ("a" 1) ("b" 2)))
+    (map-insert m "c" 3)
+    (map-remove m "a")
+    (tuple m (map-keys m) (map-get m "b") (map-contains m "a"))))
@@ -0,0 +1,2 @@
+(import geometry as g
+  (tuple (g/area (g/square 3)) (g/perimeter (g/square 3))))
@@ -0,0 +1,5 @@
+(module
+  (def Rect (struct (w i64) (h i64)))
+  (def square (fn (n) (record Rect (w n) (h n))))
+  (def area (fn ((r Rect)) (* (field-get r w) (field-get r h))))
+  (def perimeter (fn ((r Rect)) (* 2 (+ (field-get r w) (field-get r h))))))
@@ -0,0 +1,3 @@
+(let div (fn (a b) (match b (0 (err "div by zero")) (_ (ok (/ a b)))))
+  (let g (fn (a b c) (ok (+ (? (div a b)) (? (div a c)))))
+    (tuple (g 10 2 5) (g 10 0 5))))
@@ -0,0 +1 @@
+(tuple (max 3 (abs -5)) (gcd 12 18) (sum (take (array 1 2 3 4) 3)) (unwrap-or (some 4) 0))
@@ -0,0 +1,8 @@
+(let f
+  (fn (o) (variant (Option i64) Some (+ (? o) 1)))
+  (let g
+    (fn (r) (seq (? r) (variant (Result i64 str) Ok 0)))
+    (tuple
+      (f (variant (Option i64) Some 41))
+      (f (variant (Option i64) None))
+      (g (variant (Result i64 str) Err "bad")))))
@@ -0,0 +1,2 @@
+(let x 5
+  (tuple '(a b 1 "s") `(+ ,x ,@(array 'y '2)) (eval `(* ,x 4) i64)))
@@ -0,0 +1,9 @@
+(let catch-all
+  (fn (f n) (try (seq (f n) "") (catch (e str) e)))
+  (array
+    (catch-all (fn (n) (/ 1 n)) 0)
+    (catch-all (fn (n) (array-get (array 1 2) n)) 5)
+    (catch-all (fn (n) (array-len (array-slice (array 1 2) 1 n))) 3)
+    (catch-all (fn (n) (map-get (map (1 "a")) n)) 2)
+    (catch-all (fn (n) (array-len (array-fill n 0))) -1)
+    (catch-all (fn (n) (var a (array-t i64) (seq (array-push a n) (array-pop a) (array-pop a)))) 1)))
@@ -0,0 +1 @@
+(let s "tab\there \"quoted\"\nnext line" s)
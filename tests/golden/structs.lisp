@@ -0,0 +1,6 @@
+(let P (struct (x i64) (y i64))
+  (var p P
+    (seq
+      (set p (record P (y 2) (x 1)))
+      (field-set p x 5)
+      p)))
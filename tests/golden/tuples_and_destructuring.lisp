@@ -0,0 +1,2 @@
+(let (a (b c)) (tuple 1 (tuple 2 3))
+  (var t (tuple-t i64 str) (seq (set t (tuple (+ a b c) "sum")) t)))